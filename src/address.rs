@@ -0,0 +1,300 @@
+use crate::discovery;
+use crate::error::Error;
+use crate::keylight::DEFAULT_PORT;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::lookup_host;
+use tokio::time::timeout;
+
+/// Where to find a light: an IPv4 or IPv6 address or a hostname, with an optional port.
+///
+/// Written as `192.168.0.25`, `fe80::1%eth0`, `keylight.local`, or with a port as
+/// `192.168.0.25:9124`, `[fe80::1%eth0]:9124` or `keylight.local:9124`. Link-local IPv6 addresses
+/// need a zone after the `%`, either the name or the index of the network interface.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Address {
+    host: String,
+    port: u16,
+}
+
+impl Address {
+    pub fn new(ip_address: IpAddr, port: u16) -> Address {
+        Address {
+            host: ip_address.to_string(),
+            port,
+        }
+    }
+
+    /// Looks up the socket address to connect to, waiting up to `wait` for a hostname to resolve.
+    ///
+    /// IPv4 addresses are preferred when a hostname has both. Hostnames ending in `.local` that
+    /// the system can't resolve are looked up with mDNS.
+    pub async fn resolve(&self, wait: Duration) -> Result<SocketAddr, Error> {
+        if let Ok(ip_address) = self.host.parse::<IpAddr>() {
+            if matches!(ip_address, IpAddr::V6(ip) if ip.is_unicast_link_local()) {
+                return Err(
+                    self.unreachable("link-local addresses need a zone, such as fe80::1%eth0")
+                );
+            }
+            return Ok(SocketAddr::new(ip_address, self.port));
+        }
+        if let Some((ip_address, zone)) = split_zone(&self.host) {
+            let scope_id = scope_id(zone).ok_or_else(|| {
+                self.unreachable(&format!("there is no network interface {}", zone))
+            })?;
+            return Ok(SocketAddrV6::new(ip_address, self.port, 0, scope_id).into());
+        }
+
+        let found = match timeout(wait, lookup_host((self.host.as_str(), self.port))).await {
+            Ok(Ok(addresses)) => addresses.min_by_key(SocketAddr::is_ipv6),
+            _ => None,
+        };
+        if let Some(address) = found {
+            return Ok(address);
+        }
+
+        if self.host.ends_with(".local") {
+            if let Some(ip_address) = discovery::resolve_hostname(&self.host, wait).await {
+                return Ok(SocketAddr::new(ip_address, self.port));
+            }
+        }
+
+        Err(self.unreachable("the hostname does not resolve"))
+    }
+
+    fn unreachable(&self, reason: &str) -> Error {
+        Error::Unreachable {
+            address: self.to_string(),
+            reason: reason.to_string(),
+            attempts: 1,
+        }
+    }
+}
+
+/// Splits `fe80::1%eth0` into the IPv6 address and its zone.
+fn split_zone(host: &str) -> Option<(Ipv6Addr, &str)> {
+    let (ip_address, zone) = host.split_once('%')?;
+    let valid_zone = !zone.is_empty()
+        && zone
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    valid_zone.then_some((ip_address.parse().ok()?, zone))
+}
+
+/// The index of the network interface a zone names, which is either the index itself or the
+/// interface's name.
+fn scope_id(zone: &str) -> Option<u32> {
+    if let Ok(index) = zone.parse() {
+        return Some(index);
+    }
+    interface_index(zone)
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: `name` is a valid NUL-terminated string that outlives the call.
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip_address) = s.parse::<IpAddr>() {
+            return Ok(Address::new(ip_address, DEFAULT_PORT));
+        }
+
+        let invalid = || format!("Invalid address \"{}\"", s);
+        let zoned = |host: &str, port| {
+            let (ip_address, zone) = split_zone(host).ok_or_else(invalid)?;
+            Ok(Address {
+                host: format!("{}%{}", ip_address, zone),
+                port,
+            })
+        };
+        if s.contains('%') {
+            return match s.strip_prefix('[').and_then(|s| s.rsplit_once("]:")) {
+                Some((host, port)) => zoned(host, port.parse().map_err(|_| invalid())?),
+                None => zoned(s, DEFAULT_PORT),
+            };
+        }
+        if let Ok(address) = s.parse::<SocketAddr>() {
+            return Ok(Address::new(address.ip(), address.port()));
+        }
+
+        let (host, port) = match s.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (s, DEFAULT_PORT),
+        };
+        if !is_hostname(host) {
+            return Err(invalid());
+        }
+
+        Ok(Address {
+            host: host.trim_end_matches('.').to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+/// Whether `host` is a valid DNS name. The last label can't be all digits, so a mistyped IPv4
+/// address like `192.168.0` isn't taken for a hostname.
+fn is_hostname(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let labels: Vec<&str> = host.split('.').collect();
+    let valid_label = |label: &&str| {
+        (1..=63).contains(&label.len())
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+
+    host.len() <= 253
+        && labels.iter().all(valid_label)
+        && labels
+            .last()
+            .is_some_and(|label| !label.chars().all(|c| c.is_ascii_digit()))
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ipv6 = self.host.contains(':');
+        match (self.port == DEFAULT_PORT, ipv6) {
+            (true, _) => write!(f, "{}", self.host),
+            (false, true) => write!(f, "[{}]:{}", self.host, self.port),
+            (false, false) => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> String {
+        address.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(host: &str, port: u16) -> Address {
+        Address {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn parses_ip_addresses_with_and_without_a_port() {
+        assert_eq!("192.168.0.25".parse(), Ok(address("192.168.0.25", 9123)));
+        assert_eq!("192.168.0.25:80".parse(), Ok(address("192.168.0.25", 80)));
+        assert_eq!("fe80::1".parse(), Ok(address("fe80::1", 9123)));
+        assert_eq!("[FE80::1]:80".parse(), Ok(address("fe80::1", 80)));
+    }
+
+    #[test]
+    fn parses_ipv6_addresses_with_a_zone() {
+        assert_eq!("fe80::1%eth0".parse(), Ok(address("fe80::1%eth0", 9123)));
+        assert_eq!("[FE80::1%2]:80".parse(), Ok(address("fe80::1%2", 80)));
+        assert_eq!(address("fe80::1%en0", 80).to_string(), "[fe80::1%en0]:80");
+        for s in [
+            "fe80::1%",
+            "[fe80::1%eth0]",
+            "fe80::1%eth 0",
+            "192.168.0.25%eth0",
+        ] {
+            assert!(s.parse::<Address>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parses_hostnames_with_and_without_a_port() {
+        assert_eq!(
+            "keylight.local".parse(),
+            Ok(address("keylight.local", 9123))
+        );
+        assert_eq!(
+            "KeyLight.local.:80".parse(),
+            Ok(address("keylight.local", 80))
+        );
+        assert_eq!("keylight".parse(), Ok(address("keylight", 9123)));
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        for s in [
+            "",
+            "192.168.0",
+            "192.168.0.256",
+            "keylight.local:port",
+            "keylight.local:99999",
+            "key light",
+            "-keylight",
+            "[fe80::1]",
+        ] {
+            assert!(s.parse::<Address>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn displays_the_port_only_when_it_is_not_the_default() {
+        assert_eq!(address("192.168.0.25", 9123).to_string(), "192.168.0.25");
+        assert_eq!(address("fe80::1", 80).to_string(), "[fe80::1]:80");
+        assert_eq!(
+            address("keylight.local", 80).to_string(),
+            "keylight.local:80"
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_ip_addresses_and_hostnames() {
+        let wait = Duration::from_secs(2);
+        assert_eq!(
+            address("::1", 80).resolve(wait).await.unwrap(),
+            "[::1]:80".parse().unwrap()
+        );
+        assert!(address("localhost", 80)
+            .resolve(wait)
+            .await
+            .unwrap()
+            .ip()
+            .is_loopback());
+    }
+
+    #[tokio::test]
+    async fn keeps_the_zone_of_link_local_addresses() {
+        let wait = Duration::from_secs(2);
+        let resolved = address("fe80::1%2", 80).resolve(wait).await.unwrap();
+        assert_eq!(resolved, "[fe80::1%2]:80".parse().unwrap());
+        let SocketAddr::V6(resolved) = resolved else {
+            panic!("{} is not IPv6", resolved);
+        };
+        assert_eq!(resolved.scope_id(), 2);
+
+        assert!(address("fe80::1", 80).resolve(wait).await.is_err());
+        assert!(address("fe80::1%no-such-interface", 80)
+            .resolve(wait)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,46 @@
+//! Serves a mock Elgato light, for trying the CLI without one.
+
+use elgato_light::mock::MockLight;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "mock-light",
+    about = "Serves a mock Elgato light that answers like a real one"
+)]
+struct Args {
+    #[structopt(
+        short = "a",
+        long = "address",
+        default_value = "127.0.0.1:9123",
+        help = "Address to listen on"
+    )]
+    address: SocketAddr,
+
+    #[structopt(
+        short = "n",
+        long = "lights",
+        default_value = "1",
+        help = "Number of lights on the device"
+    )]
+    lights: usize,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::from_args();
+    let light = match MockLight::bind(args.address, args.lights).await {
+        Ok(light) => light,
+        Err(e) => {
+            eprintln!("Error: Unable to listen on {}: {}", args.address, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("Listening on {}", light.address());
+
+    std::future::pending::<()>().await;
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+pub const MAX_BRIGHTNESS: u8 = 100;
+
+/// Parses a brightness percentage from the command line.
+pub fn parse_brightness(s: &str) -> Result<u8, String> {
+    s.trim_end_matches('%')
+        .parse()
+        .ok()
+        .filter(|brightness| *brightness <= MAX_BRIGHTNESS)
+        .ok_or_else(|| format!("Brightness must be between 0 and 100, got {}", s))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BrightnessChange {
+    Set(u8),
+    By(i32),
+}
+
+impl BrightnessChange {
+    /// A change to an absolute brightness, which must be between 0 and 100.
+    pub fn set(brightness: i32) -> Result<BrightnessChange, String> {
+        u8::try_from(brightness)
+            .ok()
+            .filter(|brightness| *brightness <= MAX_BRIGHTNESS)
+            .map(BrightnessChange::Set)
+            .ok_or_else(|| format!("Brightness must be between 0 and 100, got {}", brightness))
+    }
+
+    /// Applies the change to `current`, clamping the result to 0-100.
+    pub fn apply(self, current: u8) -> u8 {
+        match self {
+            BrightnessChange::Set(brightness) => brightness.min(MAX_BRIGHTNESS),
+            BrightnessChange::By(delta) => i32::from(current)
+                .saturating_add(delta)
+                .clamp(0, i32::from(MAX_BRIGHTNESS))
+                as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_changes_clamp_at_the_ends_of_the_range() {
+        assert_eq!(BrightnessChange::By(50).apply(100), 100);
+        assert_eq!(BrightnessChange::By(127).apply(100), 100);
+        assert_eq!(BrightnessChange::By(1).apply(99), 100);
+        assert_eq!(BrightnessChange::By(-50).apply(0), 0);
+        assert_eq!(BrightnessChange::By(-1).apply(1), 0);
+        assert_eq!(BrightnessChange::By(-128).apply(100), 0);
+        assert_eq!(BrightnessChange::By(0).apply(42), 42);
+    }
+
+    #[test]
+    fn relative_changes_do_not_overflow() {
+        assert_eq!(BrightnessChange::By(i32::MAX).apply(255), 100);
+        assert_eq!(BrightnessChange::By(i32::MIN).apply(0), 0);
+    }
+
+    #[test]
+    fn absolute_changes_must_be_a_percentage() {
+        assert_eq!(BrightnessChange::set(0), Ok(BrightnessChange::Set(0)));
+        assert_eq!(BrightnessChange::set(100), Ok(BrightnessChange::Set(100)));
+        assert!(BrightnessChange::set(101).is_err());
+        assert!(BrightnessChange::set(-1).is_err());
+        assert_eq!(BrightnessChange::Set(40).apply(90), 40);
+    }
+
+    #[test]
+    fn parses_percentages() {
+        assert_eq!(parse_brightness("0"), Ok(0));
+        assert_eq!(parse_brightness("100"), Ok(100));
+        assert_eq!(parse_brightness("40%"), Ok(40));
+        assert!(parse_brightness("101").is_err());
+        assert!(parse_brightness("-1").is_err());
+        assert!(parse_brightness("bright").is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::address::Address;
+use crate::brightness::parse_brightness;
+use crate::discovery;
+use crate::error::Error;
+use crate::fade::parse_duration;
+use crate::temperature::Temperature;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml_edit::{value, DocumentMut, Item, Table};
+
+pub const CONFIG_ENV_VAR: &str = "ELGATO_LIGHT_CONFIG";
+pub const DEFAULT_BRIGHTNESS: u8 = 10;
+
+/// Settings read from `config.toml`. Anything missing falls back to the built-in defaults.
+///
+/// ```toml
+/// default_light = "desk"
+/// brightness = 10
+/// temperature = 3000
+/// timeout = "2s"
+/// retries = 3
+///
+/// [lights.desk]
+/// ip_address = "192.168.0.25"
+/// brightness = 20
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub default_light: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_brightness")]
+    pub brightness: Option<u8>,
+    pub temperature: Option<Temperature>,
+    /// How long to wait for a light to answer each request.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    /// How many times to try a request again when a light doesn't answer.
+    pub retries: Option<u32>,
+    #[serde(default)]
+    pub lights: BTreeMap<String, LightConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightConfig {
+    /// An IP address or hostname, with an optional port.
+    pub ip_address: Address,
+    #[serde(default, deserialize_with = "deserialize_brightness")]
+    pub brightness: Option<u8>,
+    pub temperature: Option<Temperature>,
+}
+
+impl Config {
+    /// Loads the config from `path`, or from the default location when no path is given.
+    ///
+    /// A missing file at the default location is not an error, but a missing file that was
+    /// explicitly asked for is.
+    pub fn load(path: Option<&Path>) -> Result<Config, Error> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Config::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => return Ok(Config::default()),
+            Err(e) => {
+                return Err(Error::Config(format!(
+                    "Unable to read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+
+        toml::from_str(&contents)
+            .map_err(|e| Error::Config(format!("Invalid config file {}: {}", path.display(), e)))
+    }
+
+    /// The path to write to: `path` when given, otherwise the default location.
+    pub fn path(path: Option<&Path>) -> Result<PathBuf, Error> {
+        match path {
+            Some(path) => Ok(path.to_path_buf()),
+            None => Config::default_path().ok_or_else(|| {
+                Error::Config("Unable to find the config directory. Use --config".to_string())
+            }),
+        }
+    }
+
+    /// `$XDG_CONFIG_HOME/elgato-light/config.toml`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(xdg_dir("XDG_CONFIG_HOME", ".config")?.join("config.toml"))
+    }
+
+    pub fn brightness(&self, ip_address: &Address) -> u8 {
+        self.light(ip_address)
+            .and_then(|light| light.brightness)
+            .or(self.brightness)
+            .unwrap_or(DEFAULT_BRIGHTNESS)
+    }
+
+    pub fn temperature(&self, ip_address: &Address) -> Temperature {
+        self.light(ip_address)
+            .and_then(|light| light.temperature)
+            .or(self.temperature)
+            .unwrap_or_default()
+    }
+
+    /// The address of a light by its name in the config file, or else from the last discovery.
+    pub fn resolve(&self, name: &str) -> Result<Address, Error> {
+        if let Some(light) = self.lights.get(name) {
+            return Ok(light.ip_address.clone());
+        }
+
+        discovery::find_cached(name)
+            .map(|light| Address::new(light.ip_address, light.port))
+            .ok_or_else(|| Error::InvalidAddress(format!("Unknown light \"{}\"", name)))
+    }
+
+    fn light(&self, ip_address: &Address) -> Option<&LightConfig> {
+        self.lights
+            .values()
+            .find(|light| light.ip_address == *ip_address)
+    }
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = String::deserialize(deserializer)?;
+    parse_duration(&duration)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_brightness<'de, D>(deserializer: D) -> Result<Option<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let brightness = i64::deserialize(deserializer)?;
+    parse_brightness(&brightness.to_string())
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Adds the named light to the config file at `path`, replacing any light with the same name.
+///
+/// The file is edited in place so comments and formatting elsewhere in it are kept.
+pub fn add_light(
+    path: &Path,
+    name: &str,
+    light: &LightConfig,
+    make_default: bool,
+) -> Result<(), Error> {
+    let mut document = read_document(path)?;
+
+    let mut table = Table::new();
+    table["ip_address"] = value(light.ip_address.to_string());
+    if let Some(brightness) = light.brightness {
+        table["brightness"] = value(i64::from(brightness));
+    }
+    if let Some(temperature) = light.temperature {
+        table["temperature"] = value(i64::from(temperature.kelvin()));
+    }
+
+    lights_table(&mut document, path)?.insert(name, Item::Table(table));
+    if make_default {
+        document["default_light"] = value(name);
+    }
+
+    write_file(path, &document.to_string())
+}
+
+/// Removes the named light from the config file at `path`, returning whether it was there.
+pub fn remove_light(path: &Path, name: &str) -> Result<bool, Error> {
+    let mut document = read_document(path)?;
+
+    if lights_table(&mut document, path)?.remove(name).is_none() {
+        return Ok(false);
+    }
+    if document.get("default_light").and_then(Item::as_str) == Some(name) {
+        document.remove("default_light");
+    }
+
+    write_file(path, &document.to_string())?;
+    Ok(true)
+}
+
+fn read_document(path: &Path) -> Result<DocumentMut, Error> {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents
+            .parse()
+            .map_err(|e| Error::Config(format!("Invalid config file {}: {}", path.display(), e))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(DocumentMut::new()),
+        Err(e) => Err(Error::Config(format!(
+            "Unable to read {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+/// Writes `contents` to `path`, creating its directory when it doesn't exist yet.
+pub fn write_file(path: &Path, contents: &str) -> Result<(), Error> {
+    let write = || {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)
+    };
+
+    write().map_err(|e| Error::Config(format!("Unable to write {}: {}", path.display(), e)))
+}
+
+fn lights_table<'a>(document: &'a mut DocumentMut, path: &Path) -> Result<&'a mut Table, Error> {
+    let lights = document.entry("lights").or_insert_with(|| {
+        let mut lights = Table::new();
+        lights.set_implicit(true);
+        Item::Table(lights)
+    });
+
+    lights.as_table_mut().ok_or_else(|| {
+        Error::Config(format!(
+            "Invalid config file {}: lights is not a table",
+            path.display()
+        ))
+    })
+}
+
+/// `$<var>/elgato-light`, falling back to `~/<fallback>/elgato-light` when the variable is unset.
+pub fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))?;
+
+    Some(base.join("elgato-light"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brightness_must_be_a_percentage() {
+        let config: Config = toml::from_str(
+            r#"
+            brightness = 100
+
+            [lights.desk]
+            ip_address = "192.168.0.25"
+            brightness = 0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.brightness, Some(100));
+        assert_eq!(config.lights["desk"].brightness, Some(0));
+
+        assert!(toml::from_str::<Config>("brightness = 150").is_err());
+        assert!(toml::from_str::<Config>("brightness = -1").is_err());
+        assert!(toml::from_str::<Config>(
+            r#"
+            [lights.desk]
+            ip_address = "192.168.0.25"
+            brightness = 101
+            "#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use crate::controller::{LightController, LightTarget, Operation, Outcome};
+#[cfg(unix)]
+use crate::daemon;
+use crate::error::Error;
+use crate::fade::Fade;
+use crate::keylight::RequestOptions;
+use std::path::Path;
+
+/// Where operations on lights run: on this process, or on the daemon when it's running.
+#[derive(Debug, Clone)]
+pub enum Connector {
+    Direct(RequestOptions),
+    /// The daemon, which waits for lights and tries again with these options rather than its own.
+    #[cfg(unix)]
+    Daemon(daemon::Client, RequestOptions),
+}
+
+impl Connector {
+    /// Uses the daemon listening on `socket` when there is one, and otherwise connects to lights
+    /// directly with `options`.
+    pub async fn new(socket: Option<&Path>, options: RequestOptions) -> Connector {
+        #[cfg(unix)]
+        if let Some(path) = socket {
+            if let Some(client) = daemon::Client::connect(path).await {
+                return Connector::Daemon(client, options);
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = socket;
+
+        Connector::Direct(options)
+    }
+
+    pub async fn perform(
+        &self,
+        target: &LightTarget,
+        fade: Option<Fade>,
+        operation: Operation,
+    ) -> Result<Outcome, Error> {
+        match self {
+            Connector::Direct(options) => {
+                let mut light = LightController::connect(target, *options).await?;
+                light.set_fade(fade);
+                light.perform(operation).await
+            }
+            #[cfg(unix)]
+            Connector::Daemon(client, options) => {
+                client.perform(target, fade, operation, *options).await
+            }
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use crate::address::Address;
+use crate::brightness::BrightnessChange;
+use crate::error::Error;
+use crate::fade::Fade;
+use crate::keylight::{AccessoryInfo, KeyLight, Light, LightUpdate, RequestOptions, Settings};
+use crate::settings::SettingsChange;
+use crate::temperature::{Temperature, TemperatureAdjustment};
+use serde::{Deserialize, Serialize};
+
+/// The light or lights to control: a device's address and, optionally, one light on it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LightTarget {
+    pub address: Address,
+    /// Position of the light on the device, starting at 0. `None` is every light on the device.
+    pub index: Option<usize>,
+}
+
+impl LightTarget {
+    /// Every light on the device at `address`.
+    pub fn new(address: Address) -> LightTarget {
+        LightTarget {
+            address,
+            index: None,
+        }
+    }
+
+    /// Only the light at `index` on the device.
+    pub fn with_index(self, index: usize) -> LightTarget {
+        LightTarget {
+            index: Some(index),
+            ..self
+        }
+    }
+}
+
+/// The state of one light on a device, in percent and Kelvin.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LightState {
+    /// Position of the light on the device, starting at 0.
+    pub index: usize,
+    pub on: bool,
+    /// Brightness in percent (0-100).
+    pub brightness: u8,
+    pub temperature: Temperature,
+}
+
+impl LightState {
+    fn from_device(index: usize, light: &Light) -> LightState {
+        LightState {
+            index,
+            on: light.on != 0,
+            brightness: light.brightness,
+            temperature: Temperature::from_device(light.temperature),
+        }
+    }
+}
+
+/// Controls the targeted lights on one device.
+///
+/// ```no_run
+/// use elgato_light::{LightController, LightTarget, RequestOptions};
+/// use elgato_light::brightness::BrightnessChange;
+///
+/// # async fn example() -> Result<(), elgato_light::Error> {
+/// let target = LightTarget::new("192.168.0.25".parse().unwrap());
+/// let light = LightController::connect(&target, RequestOptions::default()).await?;
+///
+/// light.adjust_brightness(BrightnessChange::By(10)).await?;
+/// for state in light.state().await? {
+///     println!("{}: {}%", state.index, state.brightness);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct LightController {
+    keylight: KeyLight,
+}
+
+impl LightController {
+    /// Resolves the target's address and connects to the device, checking that the index is on
+    /// it.
+    pub async fn connect(
+        target: &LightTarget,
+        options: RequestOptions,
+    ) -> Result<LightController, Error> {
+        let address = target.address.resolve(options.timeout).await?;
+        let mut keylight = KeyLight::new_from_ip(address, options).await?;
+        keylight.select(target.index)?;
+
+        Ok(LightController { keylight })
+    }
+
+    /// Limits changes to the light at `index` on the device, or to every light when `None`.
+    pub fn select(&mut self, index: Option<usize>) -> Result<(), Error> {
+        self.keylight.select(index)
+    }
+
+    /// Changes how long to wait for the device to answer, and how many times to try again.
+    pub fn set_options(&mut self, options: RequestOptions) {
+        self.keylight.set_options(options);
+    }
+
+    /// Makes changes fade in over time instead of happening at once.
+    pub fn set_fade(&mut self, fade: Option<Fade>) {
+        self.keylight.set_fade(fade);
+    }
+
+    /// The device's HTTP client, for the parts of its API beyond the lights.
+    pub fn keylight(&self) -> &KeyLight {
+        &self.keylight
+    }
+
+    /// The state of each targeted light.
+    pub async fn state(&self) -> Result<Vec<LightState>, Error> {
+        let status = self.keylight.get().await?;
+
+        Ok(status
+            .lights
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| self.keylight.is_selected(*index))
+            .map(|(index, light)| LightState::from_device(index, light))
+            .collect())
+    }
+
+    /// Sets the power, brightness and temperature of the targeted lights at once.
+    pub async fn set_state(
+        &self,
+        on: bool,
+        brightness: u8,
+        temperature: Temperature,
+    ) -> Result<(), Error> {
+        self.keylight.set_state(on, brightness, temperature).await
+    }
+
+    /// Changes only the given fields of the targeted lights, in a single request.
+    pub async fn change(
+        &self,
+        on: Option<bool>,
+        brightness: Option<u8>,
+        temperature: Option<Temperature>,
+    ) -> Result<(), Error> {
+        self.keylight
+            .put(LightUpdate {
+                on: on.map(u8::from),
+                brightness: brightness.map(|brightness| brightness.min(100)),
+                temperature: temperature.map(Temperature::to_device),
+            })
+            .await
+    }
+
+    /// Puts each light back in a state from [`LightController::state`], whether or not it's
+    /// targeted. Lights not in `states` are left as they are.
+    pub async fn restore(&self, states: &[LightState]) -> Result<(), Error> {
+        let mut lights = vec![LightUpdate::default(); self.keylight.number_of_lights()];
+        for state in states {
+            if let Some(light) = lights.get_mut(state.index) {
+                *light = LightUpdate {
+                    on: Some(state.on as u8),
+                    brightness: Some(state.brightness),
+                    temperature: Some(state.temperature.to_device()),
+                };
+            }
+        }
+
+        self.keylight.set_lights(&lights).await
+    }
+
+    pub async fn set_power(&self, on: bool) -> Result<(), Error> {
+        self.keylight.set_power(on).await
+    }
+
+    /// Turns the targeted lights off if any of them are on, and on otherwise, returning whether
+    /// they're now on. They keep their brightness and temperature while off.
+    pub async fn toggle(&self) -> Result<bool, Error> {
+        let any_on = self.state().await?.iter().any(|state| state.on);
+        self.keylight.set_power(!any_on).await?;
+        Ok(!any_on)
+    }
+
+    /// Changes the brightness of each targeted light based on its own, turning it on.
+    pub async fn adjust_brightness(&self, change: BrightnessChange) -> Result<(), Error> {
+        self.keylight
+            .update(|light| LightUpdate {
+                on: Some(1),
+                brightness: Some(change.apply(light.brightness)),
+                ..Default::default()
+            })
+            .await
+    }
+
+    /// Changes the temperature of each targeted light based on its own, turning it on.
+    pub async fn adjust_temperature(&self, change: TemperatureAdjustment) -> Result<(), Error> {
+        self.keylight
+            .update(|light| {
+                let temperature = change.apply(Temperature::from_device(light.temperature));
+                LightUpdate {
+                    on: Some(1),
+                    temperature: Some(temperature.to_device()),
+                    ..Default::default()
+                }
+            })
+            .await
+    }
+
+    /// Runs an operation against the targeted lights.
+    pub async fn perform(&self, operation: Operation) -> Result<Outcome, Error> {
+        match operation {
+            Operation::State => {
+                let lights = self.state().await?;
+                let info = self.keylight.accessory_info().await?;
+                return Ok(Outcome::State {
+                    name: info.name().to_string(),
+                    lights,
+                });
+            }
+            Operation::SetState {
+                on,
+                brightness,
+                temperature,
+            } => self.set_state(on, brightness, temperature).await?,
+            Operation::Change {
+                on,
+                brightness,
+                temperature,
+            } => self.change(on, brightness, temperature).await?,
+            Operation::SetPower(on) => self.set_power(on).await?,
+            Operation::Toggle => {
+                self.toggle().await?;
+            }
+            Operation::AdjustBrightness(change) => self.adjust_brightness(change).await?,
+            Operation::AdjustTemperature(change) => self.adjust_temperature(change).await?,
+            Operation::Restore(states) => self.restore(&states).await?,
+            Operation::Info => return Ok(Outcome::Info(self.keylight.accessory_info().await?)),
+            Operation::Settings => return Ok(Outcome::Settings(self.keylight.settings().await?)),
+            Operation::ChangeSettings(change) => {
+                let mut settings = self.keylight.settings().await?;
+                change.apply(&mut settings);
+                self.keylight.set_settings(&settings).await?;
+            }
+            Operation::Identify => self.keylight.identify().await?,
+            Operation::Rename(name) => self.keylight.set_display_name(&name).await?,
+        }
+
+        Ok(Outcome::Done)
+    }
+}
+
+/// Something to do to the targeted lights, as a value so it can be sent to the daemon and run
+/// there. See [`LightController::perform`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Operation {
+    /// Reads the device's name and the state of each targeted light.
+    State,
+    SetState {
+        on: bool,
+        brightness: u8,
+        temperature: Temperature,
+    },
+    /// Sets the fields that are given and leaves the others as they are.
+    Change {
+        on: Option<bool>,
+        brightness: Option<u8>,
+        temperature: Option<Temperature>,
+    },
+    SetPower(bool),
+    Toggle,
+    AdjustBrightness(BrightnessChange),
+    AdjustTemperature(TemperatureAdjustment),
+    Restore(Vec<LightState>),
+    /// Reads what the device says about itself.
+    Info,
+    /// Reads the device's settings.
+    Settings,
+    /// Changes the given settings and keeps the others.
+    ChangeSettings(SettingsChange),
+    Identify,
+    Rename(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Outcome {
+    Done,
+    /// The device's name, see [`AccessoryInfo::name`], and the state of each targeted light.
+    ///
+    /// [`AccessoryInfo::name`]: crate::keylight::AccessoryInfo::name
+    State {
+        name: String,
+        lights: Vec<LightState>,
+    },
+    Info(AccessoryInfo),
+    Settings(Settings),
+}
@@ -0,0 +1,415 @@
+//! A long-running process that keeps lights connected, so commands don't look up and connect to
+//! each light every time they run.
+//!
+//! Commands reach it over a Unix socket, sending a [`Request`] as a line of JSON and reading back
+//! a [`Response`] the same way.
+
+use crate::address::Address;
+use crate::config;
+use crate::controller::{LightController, LightTarget, Operation, Outcome};
+use crate::error::Error;
+use crate::fade::Fade;
+use crate::keylight::{AccessoryInfo, RequestOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// `$XDG_RUNTIME_DIR/elgato-light.sock`, or `daemon.sock` in the cache directory when there's no
+/// runtime directory.
+pub fn socket_path() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("elgato-light.sock"));
+    }
+    Some(config::xdg_dir("XDG_CACHE_HOME", ".cache")?.join("daemon.sock"))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub target: LightTarget,
+    pub fade: Option<Fade>,
+    pub operation: Operation,
+    /// The client's timeout and retries, used instead of the daemon's own.
+    #[serde(default)]
+    pub options: Option<RequestOptions>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    Ok(Outcome),
+    Err { exit_code: u8, message: String },
+}
+
+impl Response {
+    pub fn into_result(self) -> Result<Outcome, Error> {
+        match self {
+            Response::Ok(outcome) => Ok(outcome),
+            Response::Err { exit_code, message } => Err(Error::Daemon { exit_code, message }),
+        }
+    }
+}
+
+impl From<Result<Outcome, Error>> for Response {
+    fn from(result: Result<Outcome, Error>) -> Self {
+        match result {
+            Ok(outcome) => Response::Ok(outcome),
+            Err(e) => Response::Err {
+                exit_code: e.exit_code(),
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+/// Keeps a connection to each light it has been asked about, with its address looked up and its
+/// number of lights read, and runs operations on them.
+///
+/// The accessory info of each device is kept too, so reading the status only reads the lights.
+#[derive(Debug)]
+pub struct Daemon {
+    options: RequestOptions,
+    lights: Mutex<HashMap<Address, LightController>>,
+    infos: Mutex<HashMap<Address, AccessoryInfo>>,
+}
+
+impl Daemon {
+    pub fn new(options: RequestOptions) -> Daemon {
+        Daemon {
+            options,
+            lights: Mutex::new(HashMap::new()),
+            infos: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The connection to the light at `address`, connecting to it with `options` the first time.
+    pub async fn connect(
+        &self,
+        address: &Address,
+        options: RequestOptions,
+    ) -> Result<LightController, Error> {
+        if let Some(light) = self.lights.lock().unwrap().get(address) {
+            return Ok(light.clone());
+        }
+
+        let light = LightController::connect(&LightTarget::new(address.clone()), options).await?;
+        self.lights
+            .lock()
+            .unwrap()
+            .insert(address.clone(), light.clone());
+        Ok(light)
+    }
+
+    pub async fn handle(&self, request: Request) -> Result<Outcome, Error> {
+        let options = request.options.unwrap_or(self.options);
+        let mut light = self.connect(&request.target.address, options).await?;
+        light.set_options(options);
+        light.select(request.target.index)?;
+        light.set_fade(request.fade);
+
+        let address = &request.target.address;
+        let result = self.perform(address, &light, request.operation).await;
+        if result.as_ref().is_err_and(Error::is_retryable) {
+            // The light may have moved to another address, so look it up again next time.
+            self.lights.lock().unwrap().remove(address);
+            self.infos.lock().unwrap().remove(address);
+        }
+        result
+    }
+
+    /// Runs `operation` on `light`, with the accessory info from [`Daemon::info`].
+    async fn perform(
+        &self,
+        address: &Address,
+        light: &LightController,
+        operation: Operation,
+    ) -> Result<Outcome, Error> {
+        match operation {
+            Operation::State => {
+                let lights = light.state().await?;
+                let info = self.info(address, light).await?;
+                Ok(Outcome::State {
+                    name: info.name().to_string(),
+                    lights,
+                })
+            }
+            Operation::Info => Ok(Outcome::Info(self.info(address, light).await?)),
+            Operation::Rename(name) => {
+                let result = light.perform(Operation::Rename(name)).await;
+                self.infos.lock().unwrap().remove(address);
+                result
+            }
+            operation => light.perform(operation).await,
+        }
+    }
+
+    /// The accessory info of the device at `address`, reading it from `light` the first time.
+    async fn info(
+        &self,
+        address: &Address,
+        light: &LightController,
+    ) -> Result<AccessoryInfo, Error> {
+        if let Some(info) = self.infos.lock().unwrap().get(address) {
+            return Ok(info.clone());
+        }
+
+        let info = light.keylight().accessory_info().await?;
+        self.infos
+            .lock()
+            .unwrap()
+            .insert(address.clone(), info.clone());
+        Ok(info)
+    }
+
+    /// Answers requests on the socket at `path`, from [`listen`], until the process is
+    /// interrupted or terminated, then removes the socket.
+    pub async fn run(self: Arc<Self>, listener: UnixListener, path: &Path) -> Result<(), Error> {
+        let mut terminate = signal(SignalKind::terminate())
+            .map_err(|e| Error::Other(format!("Unable to listen for signals: {}", e)))?;
+
+        let result = tokio::select! {
+            result = self.serve(listener) => result,
+            _ = tokio::signal::ctrl_c() => Ok(()),
+            _ = terminate.recv() => Ok(()),
+        };
+
+        let _ = fs::remove_file(path);
+        result
+    }
+
+    async fn serve(self: Arc<Self>, listener: UnixListener) -> Result<(), Error> {
+        loop {
+            let (stream, _) = listener
+                .accept()
+                .await
+                .map_err(|e| Error::Other(format!("Unable to accept a connection: {}", e)))?;
+            let daemon = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = daemon.answer(stream).await {
+                    eprintln!("Unable to answer a request: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Answers each request sent on the connection, in order.
+    async fn answer(&self, stream: UnixStream) -> io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => Response::from(self.handle(request).await),
+                Err(e) => Response::from(Err(Error::Other(format!("Invalid request: {}", e)))),
+            };
+            let mut json = serde_json::to_string(&response)?;
+            json.push('\n');
+            writer.write_all(json.as_bytes()).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Listens on `path`, replacing a socket left behind by a daemon that didn't shut down cleanly.
+/// Only the current user can connect.
+pub async fn listen(path: &Path) -> Result<UnixListener, Error> {
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(Error::Other(format!(
+            "A daemon is already running on {}",
+            path.display()
+        )));
+    }
+
+    let unable =
+        |e: io::Error| Error::Config(format!("Unable to listen on {}: {}", path.display(), e));
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(unable(e)),
+        _ => {}
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(unable)?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(unable)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(unable)?;
+    Ok(listener)
+}
+
+/// Sends requests to a running daemon.
+#[derive(Debug, Clone)]
+pub struct Client {
+    path: PathBuf,
+}
+
+impl Client {
+    /// Connects to the daemon on the socket at `path`, or returns `None` when none is running.
+    pub async fn connect(path: &Path) -> Option<Client> {
+        UnixStream::connect(path).await.ok()?;
+        Some(Client {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Has the daemon run `operation` on the targeted lights, waiting for them and trying again
+    /// with `options`.
+    pub async fn perform(
+        &self,
+        target: &LightTarget,
+        fade: Option<Fade>,
+        operation: Operation,
+        options: RequestOptions,
+    ) -> Result<Outcome, Error> {
+        let request = Request {
+            target: target.clone(),
+            fade,
+            operation,
+            options: Some(options),
+        };
+
+        self.send(&request)
+            .await
+            .map_err(|e| {
+                Error::Other(format!(
+                    "Lost the connection to the daemon on {}: {}",
+                    self.path.display(),
+                    e
+                ))
+            })?
+            .into_result()
+    }
+
+    async fn send(&self, request: &Request) -> io::Result<Response> {
+        let stream = UnixStream::connect(&self.path).await?;
+        let (reader, mut writer) = stream.into_split();
+
+        let mut json = serde_json::to_string(request)?;
+        json.push('\n');
+        writer.write_all(json.as_bytes()).await?;
+        writer.shutdown().await?;
+
+        let mut line = String::new();
+        if BufReader::new(reader).read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "the daemon closed the connection",
+            ));
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brightness::BrightnessChange;
+    use crate::mock::{unused_port, MockLight};
+
+    #[tokio::test]
+    async fn runs_requests_on_a_connection_it_keeps() {
+        let light = MockLight::start(2).await.unwrap();
+        let target = LightTarget::new(Address::new(light.address().ip(), light.address().port()));
+        let daemon = Daemon::new(RequestOptions::default());
+
+        let request = |index, change| Request {
+            target: target.clone().with_index(index),
+            fade: None,
+            operation: Operation::AdjustBrightness(change),
+            options: None,
+        };
+        daemon
+            .handle(request(1, BrightnessChange::Set(70)))
+            .await
+            .unwrap();
+        let connected = light.requests();
+        daemon
+            .handle(request(0, BrightnessChange::By(10)))
+            .await
+            .unwrap();
+
+        // Only a read and a write for the second request, without connecting again.
+        assert_eq!(light.requests(), connected + 2);
+        let lights = light.lights();
+        assert_eq!((lights[0].brightness, lights[1].brightness), (30, 70));
+    }
+
+    #[tokio::test]
+    async fn passes_on_errors_with_their_exit_code() {
+        let light = MockLight::start(1).await.unwrap();
+        let target = LightTarget::new(Address::new(light.address().ip(), light.address().port()));
+        let daemon = Daemon::new(RequestOptions::default());
+
+        let result = daemon
+            .handle(Request {
+                target: target.with_index(3),
+                fade: None,
+                operation: Operation::Toggle,
+                options: None,
+            })
+            .await;
+        let error = Response::from(result).into_result().unwrap_err();
+
+        assert_eq!(error.exit_code(), 3);
+        assert!(error.to_string().contains("out of range"));
+    }
+
+    #[tokio::test]
+    async fn uses_the_options_sent_with_the_request() {
+        let port = unused_port();
+        let target = LightTarget::new(Address::new([127, 0, 0, 1].into(), port));
+        let daemon = Daemon::new(RequestOptions::default());
+
+        let error = daemon
+            .handle(Request {
+                target,
+                fade: None,
+                operation: Operation::Toggle,
+                options: Some(RequestOptions {
+                    retries: 0,
+                    ..RequestOptions::default()
+                }),
+            })
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::Unreachable { attempts: 1, .. }));
+    }
+
+    #[tokio::test]
+    async fn keeps_the_accessory_info_until_the_light_is_renamed() {
+        let light = MockLight::start(1).await.unwrap();
+        let target = LightTarget::new(Address::new(light.address().ip(), light.address().port()));
+        let daemon = Daemon::new(RequestOptions::default());
+        let request = |operation| Request {
+            target: target.clone(),
+            fade: None,
+            operation,
+            options: None,
+        };
+        let name = |outcome| match outcome {
+            Outcome::State { name, .. } => name,
+            outcome => panic!("{:?} is not a state", outcome),
+        };
+
+        daemon.handle(request(Operation::State)).await.unwrap();
+        let connected = light.requests();
+        let outcome = daemon.handle(request(Operation::State)).await.unwrap();
+
+        // Only the lights are read the second time.
+        assert_eq!(light.requests(), connected + 1);
+        assert_eq!(name(outcome), "Elgato Key Light");
+
+        daemon
+            .handle(request(Operation::Rename("Desk".to_string())))
+            .await
+            .unwrap();
+        let outcome = daemon.handle(request(Operation::State)).await.unwrap();
+        assert_eq!(name(outcome), "Desk");
+    }
+}
@@ -0,0 +1,179 @@
+use crate::config;
+use crate::error::Error;
+use crate::keylight::AccessoryInfo;
+use mdns_sd::{HostnameResolutionEvent, ServiceDaemon, ServiceEvent};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+
+/// The service type Elgato lights advertise themselves under.
+pub const SERVICE_TYPE: &str = "_elg._tcp.local.";
+
+/// How long to browse for when no timeout is given.
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiscoveredLight {
+    pub name: String,
+    pub ip_address: IpAddr,
+    pub port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Cache {
+    #[serde(default)]
+    lights: Vec<DiscoveredLight>,
+}
+
+/// Browses for lights advertising `service_type` until `duration` has elapsed.
+///
+/// IPv4 addresses are preferred when a light has both. The serial number is looked up from each
+/// light's accessory info after browsing, and is left empty when the light doesn't answer.
+pub async fn discover(
+    service_type: &str,
+    duration: Duration,
+) -> Result<Vec<DiscoveredLight>, Error> {
+    let mdns = ServiceDaemon::new().map_err(discovery_error)?;
+    let receiver = mdns.browse(service_type).map_err(discovery_error)?;
+    let deadline = Instant::now() + duration;
+    let mut lights: Vec<DiscoveredLight> = Vec::new();
+
+    while let Ok(Ok(event)) = timeout_at(deadline, receiver.recv_async()).await {
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+        // IPv4 addresses sort before IPv6 ones.
+        let Some(ip_address) = info.get_addresses().iter().min().copied() else {
+            continue;
+        };
+        let name = info
+            .get_fullname()
+            .trim_end_matches(info.get_type())
+            .trim_end_matches('.')
+            .to_string();
+
+        if !lights.iter().any(|light| light.name == name) {
+            lights.push(DiscoveredLight {
+                name,
+                ip_address,
+                port: info.get_port(),
+                serial_number: None,
+            });
+        }
+    }
+
+    mdns.shutdown().map_err(discovery_error)?;
+
+    for light in lights.iter_mut() {
+        light.serial_number = fetch_serial_number(light.ip_address, light.port).await.ok();
+    }
+    lights.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(lights)
+}
+
+async fn fetch_serial_number(ip_address: IpAddr, port: u16) -> Result<String, Error> {
+    let url = format!(
+        "http://{}/elgato/accessory-info",
+        SocketAddr::new(ip_address, port)
+    );
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(2))
+        .build()?;
+    let info: AccessoryInfo = client.get(url).send().await?.json().await?;
+    Ok(info.serial_number)
+}
+
+/// Looks up the address of a `.local` hostname with mDNS, for systems that don't resolve them.
+pub async fn resolve_hostname(hostname: &str, wait: Duration) -> Option<IpAddr> {
+    let mdns = ServiceDaemon::new().ok()?;
+    let hostname = format!("{}.", hostname.trim_end_matches('.'));
+    let receiver = mdns
+        .resolve_hostname(&hostname, Some(wait.as_millis() as u64))
+        .ok()?;
+
+    let mut found = None;
+    let deadline = Instant::now() + wait;
+    while let Ok(Ok(event)) = timeout_at(deadline, receiver.recv_async()).await {
+        match event {
+            HostnameResolutionEvent::AddressesFound(_, addresses) => {
+                found = addresses.into_iter().min();
+                break;
+            }
+            HostnameResolutionEvent::SearchTimeout(_) => break,
+            _ => {}
+        }
+    }
+
+    let _ = mdns.shutdown();
+    found
+}
+
+fn discovery_error(e: mdns_sd::Error) -> Error {
+    Error::Other(format!("Unable to browse for lights: {}", e))
+}
+
+/// Remembers the lights from the last discovery so they can be targeted by name.
+pub fn save_cache(lights: &[DiscoveredLight]) -> Result<(), Error> {
+    let path = cache_path()
+        .ok_or_else(|| Error::Config("Unable to find the cache directory".to_string()))?;
+    let cache = Cache {
+        lights: lights.to_vec(),
+    };
+    let contents = toml::to_string(&cache)
+        .map_err(|e| Error::Config(format!("Unable to save the discovered lights: {}", e)))?;
+    config::write_file(&path, &contents)
+}
+
+/// Looks up a light by name among the lights found by the last discovery.
+pub fn find_cached(name: &str) -> Option<DiscoveredLight> {
+    let contents = fs::read_to_string(cache_path()?).ok()?;
+    let cache: Cache = toml::from_str(&contents).ok()?;
+
+    cache
+        .lights
+        .into_iter()
+        .find(|light| light.name.eq_ignore_ascii_case(name))
+}
+
+/// `$XDG_CACHE_HOME/elgato-light/discovered.toml`, falling back to `~/.cache`.
+fn cache_path() -> Option<PathBuf> {
+    Some(config::xdg_dir("XDG_CACHE_HOME", ".cache")?.join("discovered.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdns_sd::ServiceInfo;
+
+    #[tokio::test]
+    async fn discovers_lights_from_a_local_responder() {
+        let service_type = "_elg-test._tcp.local.";
+        let responder = ServiceDaemon::new().unwrap();
+        let service = ServiceInfo::new(
+            service_type,
+            "Elgato Key Light Test",
+            "elgato-key-light-test.local.",
+            "",
+            9123,
+            &[("mf", "Elgato")][..],
+        )
+        .unwrap()
+        .enable_addr_auto();
+        responder.register(service).unwrap();
+
+        let lights = discover(service_type, Duration::from_secs(2))
+            .await
+            .unwrap();
+        responder.shutdown().unwrap();
+
+        assert_eq!(lights.len(), 1);
+        assert_eq!(lights[0].name, "Elgato Key Light Test");
+        assert_eq!(lights[0].port, 9123);
+    }
+}
@@ -0,0 +1,234 @@
+use reqwest::StatusCode;
+use std::fmt;
+
+/// Everything that can go wrong, grouped so scripts can tell the cases apart by the exit code.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// An address that can't be parsed, or a light name that doesn't resolve to one.
+    InvalidAddress(String),
+    /// A value the command can't use, such as an out of range brightness or light index.
+    InvalidInput(String),
+    /// The config, scenes or discovery cache file can't be read, written or understood.
+    Config(String),
+    /// Nothing answered at the address.
+    Unreachable {
+        address: String,
+        reason: String,
+        attempts: u32,
+    },
+    /// The light didn't answer in time.
+    Timeout {
+        address: String,
+        attempts: u32,
+    },
+    /// The light answered with an error status.
+    Http {
+        address: String,
+        status: StatusCode,
+    },
+    /// The light answered with something that isn't what an Elgato light sends.
+    UnexpectedPayload {
+        address: String,
+        reason: String,
+    },
+    /// Some of several lights failed. Each failure has already been printed.
+    LightsFailed {
+        failed: usize,
+        total: usize,
+    },
+    /// A command the daemon ran failed. Its message and exit code are passed on as they are.
+    Daemon {
+        exit_code: u8,
+        message: String,
+    },
+    Other(String),
+}
+
+impl Error {
+    /// Whether trying again might work, because the light didn't answer at all.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::Unreachable { .. } | Error::Timeout { .. })
+    }
+
+    /// Records how many attempts were made before giving up.
+    pub fn with_attempts(mut self, count: u32) -> Error {
+        if let Error::Unreachable { attempts, .. } | Error::Timeout { attempts, .. } = &mut self {
+            *attempts = count;
+        }
+        self
+    }
+
+    /// Replaces the address of the light the error is about.
+    pub fn with_address(mut self, to: String) -> Error {
+        if let Error::Unreachable { address, .. }
+        | Error::Timeout { address, .. }
+        | Error::Http { address, .. }
+        | Error::UnexpectedPayload { address, .. } = &mut self
+        {
+            *address = to;
+        }
+        self
+    }
+
+    /// The process exit code for the error. These are stable, so scripts can rely on them.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Other(_) => 1,
+            Error::InvalidAddress(_) => 2,
+            Error::InvalidInput(_) => 3,
+            Error::Config(_) => 4,
+            Error::Unreachable { .. } => 5,
+            Error::Timeout { .. } => 6,
+            Error::Http { .. } => 7,
+            Error::UnexpectedPayload { .. } => 8,
+            Error::LightsFailed { .. } => 9,
+            Error::Daemon { exit_code, .. } => *exit_code,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidAddress(message)
+            | Error::InvalidInput(message)
+            | Error::Config(message)
+            | Error::Daemon { message, .. }
+            | Error::Other(message) => write!(f, "{}", message),
+            Error::Unreachable {
+                address,
+                reason,
+                attempts,
+            } => {
+                write!(f, "Unable to reach the light at {}", address)?;
+                write_attempts(f, *attempts)?;
+                write!(f, ": {}", reason)
+            }
+            Error::Timeout { address, attempts } => {
+                write!(f, "Timed out waiting for the light at {}", address)?;
+                write_attempts(f, *attempts)
+            }
+            Error::Http { address, status } => {
+                write!(f, "The light at {} responded with HTTP {}", address, status)
+            }
+            Error::UnexpectedPayload { address, reason } => write!(
+                f,
+                "Unexpected response from the light at {}: {}",
+                address, reason
+            ),
+            Error::LightsFailed { failed, total } => {
+                write!(f, "{} of {} lights failed", failed, total)
+            }
+        }
+    }
+}
+
+fn write_attempts(f: &mut fmt::Formatter<'_>, attempts: u32) -> fmt::Result {
+    if attempts > 1 {
+        write!(f, " after {} attempts", attempts)?;
+    }
+    Ok(())
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        let address = e
+            .url()
+            .and_then(|url| {
+                Some(format!(
+                    "{}:{}",
+                    url.host_str()?,
+                    url.port_or_known_default()?
+                ))
+            })
+            .unwrap_or_else(|| "unknown address".to_string());
+
+        if e.is_timeout() {
+            Error::Timeout {
+                address,
+                attempts: 1,
+            }
+        } else if let Some(status) = e.status() {
+            Error::Http { address, status }
+        } else if e.is_decode() || e.is_body() {
+            Error::UnexpectedPayload {
+                address,
+                reason: root_cause(&e),
+            }
+        } else {
+            Error::Unreachable {
+                address,
+                reason: root_cause(&e),
+                attempts: 1,
+            }
+        }
+    }
+}
+
+/// The innermost error, which says what actually happened rather than which step failed.
+fn root_cause(e: &(dyn std::error::Error + 'static)) -> String {
+    let mut cause = e;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    cause.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::unused_port;
+    use std::collections::HashSet;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            Error::Other(String::new()),
+            Error::InvalidAddress(String::new()),
+            Error::InvalidInput(String::new()),
+            Error::Config(String::new()),
+            Error::Unreachable {
+                address: String::new(),
+                reason: String::new(),
+                attempts: 1,
+            },
+            Error::Timeout {
+                address: String::new(),
+                attempts: 1,
+            },
+            Error::Http {
+                address: String::new(),
+                status: StatusCode::NOT_FOUND,
+            },
+            Error::UnexpectedPayload {
+                address: String::new(),
+                reason: String::new(),
+            },
+            Error::LightsFailed {
+                failed: 1,
+                total: 2,
+            },
+        ];
+
+        let codes: HashSet<u8> = errors.iter().map(Error::exit_code).collect();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0));
+    }
+
+    #[tokio::test]
+    async fn refused_connections_are_unreachable() {
+        let port = unused_port();
+        let e = reqwest::get(format!("http://127.0.0.1:{}/elgato/lights", port))
+            .await
+            .unwrap_err();
+
+        let error = Error::from(e);
+        assert_eq!(error.exit_code(), 5);
+        assert!(error
+            .to_string()
+            .starts_with(&format!("Unable to reach the light at 127.0.0.1:{}", port)));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::keylight::{Light, LightUpdate};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How often a fade sends an update to the light.
+pub const STEP: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps progress through the fade, from 0 to 1, to progress through the change.
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut if t < 0.5 => 2.0 * t * t,
+            Easing::EaseInOut => 1.0 - (-2.0 * t + 2.0).powi(2) / 2.0,
+        }
+    }
+}
+
+impl FromStr for Easing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Easing::Linear),
+            "ease-in" => Ok(Easing::EaseIn),
+            "ease-out" => Ok(Easing::EaseOut),
+            "ease-in-out" => Ok(Easing::EaseInOut),
+            _ => Err(format!(
+                "Unknown easing \"{}\". Use linear, ease-in, ease-out or ease-in-out",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Fade {
+    pub duration: Duration,
+    pub easing: Easing,
+}
+
+impl Fade {
+    /// The updates to send, one every [`STEP`], to change each light from `from` to `to`.
+    ///
+    /// Brightness fades from or to zero when the light turns on or off, and a light that fades
+    /// out is left with its original brightness so it comes back at the same level.
+    pub fn frames(&self, from: &[Light], to: &[LightUpdate]) -> Vec<Vec<LightUpdate>> {
+        let steps = (self.duration.as_millis() / STEP.as_millis()).max(1) as usize;
+
+        (1..=steps)
+            .map(|step| {
+                let t = self.easing.apply(step as f64 / steps as f64);
+                from.iter()
+                    .zip(to)
+                    .map(|(from, to)| {
+                        if step == steps {
+                            final_frame(from, to)
+                        } else {
+                            frame(from, to, t)
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+fn frame(from: &Light, to: &LightUpdate, t: f64) -> LightUpdate {
+    let start_on = from.on != 0;
+    let end_on = to.on.map_or(start_on, |on| on != 0);
+    if *to == LightUpdate::default() || (!start_on && !end_on) {
+        return LightUpdate::default();
+    }
+
+    let start_brightness = if start_on { from.brightness } else { 0 };
+    let end_brightness = if end_on {
+        to.brightness.unwrap_or(from.brightness)
+    } else {
+        0
+    };
+    let end_temperature = to.temperature.unwrap_or(from.temperature);
+
+    LightUpdate {
+        on: Some(1),
+        brightness: Some(lerp(start_brightness.into(), end_brightness.into(), t) as u8),
+        temperature: Some(lerp(from.temperature.into(), end_temperature.into(), t) as u16),
+    }
+}
+
+fn final_frame(from: &Light, to: &LightUpdate) -> LightUpdate {
+    if *to == LightUpdate::default() {
+        return LightUpdate::default();
+    }
+
+    LightUpdate {
+        on: Some(to.on.unwrap_or(from.on)),
+        brightness: Some(to.brightness.unwrap_or(from.brightness)),
+        temperature: Some(to.temperature.unwrap_or(from.temperature)),
+    }
+}
+
+fn lerp(start: f64, end: f64, t: f64) -> f64 {
+    (start + (end - start) * t).round()
+}
+
+/// Parses a duration like `500ms`, `2s` or `1.5s`. A bare number is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration \"{}\"", s))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return Err(format!("Invalid duration \"{}\". Use ms, s or m", s)),
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("Invalid duration \"{}\"", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(on: u8, brightness: u8, temperature: u16) -> Light {
+        Light {
+            on,
+            brightness,
+            temperature,
+        }
+    }
+
+    fn fade(millis: u64) -> Fade {
+        Fade {
+            duration: Duration::from_millis(millis),
+            easing: Easing::Linear,
+        }
+    }
+
+    #[test]
+    fn easing_curves_start_at_zero_and_end_at_one() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+        }
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    }
+
+    #[test]
+    fn fades_brightness_and_temperature_in_steps() {
+        let to = LightUpdate {
+            brightness: Some(60),
+            temperature: Some(300),
+            ..Default::default()
+        };
+        let frames = fade(400).frames(&[light(1, 20, 200)], &[to]);
+
+        let brightness: Vec<_> = frames.iter().map(|f| f[0].brightness.unwrap()).collect();
+        let temperature: Vec<_> = frames.iter().map(|f| f[0].temperature.unwrap()).collect();
+        assert_eq!(brightness, [30, 40, 50, 60]);
+        assert_eq!(temperature, [225, 250, 275, 300]);
+    }
+
+    #[test]
+    fn fading_out_turns_off_and_keeps_the_brightness() {
+        let to = LightUpdate {
+            on: Some(0),
+            ..Default::default()
+        };
+        let frames = fade(200).frames(&[light(1, 40, 200)], &[to]);
+
+        assert_eq!(frames[0][0].brightness, Some(20));
+        assert_eq!(
+            frames[1][0],
+            LightUpdate {
+                on: Some(0),
+                brightness: Some(40),
+                temperature: Some(200),
+            }
+        );
+    }
+
+    #[test]
+    fn fading_in_starts_from_zero_brightness() {
+        let to = LightUpdate {
+            on: Some(1),
+            ..Default::default()
+        };
+        let frames = fade(200).frames(&[light(0, 40, 200)], &[to]);
+
+        assert_eq!(frames[0][0].on, Some(1));
+        assert_eq!(frames[0][0].brightness, Some(20));
+        assert_eq!(frames[1][0].brightness, Some(40));
+    }
+
+    #[test]
+    fn leaves_lights_without_changes_alone() {
+        let frames = fade(300).frames(&[light(1, 40, 200)], &[LightUpdate::default()]);
+
+        assert!(frames.iter().all(|f| f[0] == LightUpdate::default()));
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+        assert!(parse_duration("fast").is_err());
+        assert!(parse_duration("2h").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+}
@@ -0,0 +1,505 @@
+use crate::error::Error;
+use crate::fade::{self, Fade};
+use crate::temperature::Temperature;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::{self, MissedTickBehavior};
+
+pub const DEFAULT_PORT: u16 = 9123;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// How many times [`KeyLight::identify`] blinks a light that has no identify endpoint, and how long
+/// each blink lasts.
+const IDENTIFY_PULSES: usize = 3;
+const IDENTIFY_PULSE: Duration = Duration::from_millis(300);
+
+/// The hostname requests to a link-local IPv6 address are sent to, see [`KeyLight::new_from_ip`].
+const SCOPED_HOST: &str = "scoped-light.invalid";
+
+/// The wait before the first retry, doubling for each one after it up to `MAX_BACKOFF`.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(4);
+
+/// How long to wait for a light to answer, and how many times to try again when it doesn't.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RequestOptions {
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl Default for RequestOptions {
+    fn default() -> Self {
+        RequestOptions {
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+        }
+    }
+}
+
+impl RequestOptions {
+    /// The wait before retry number `retry`, starting at 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(MAX_BACKOFF)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub number_of_lights: usize,
+    pub lights: Vec<Light>,
+}
+
+/// One light as the device reports it. `temperature` is in the device's units, see
+/// [`Temperature::from_device`].
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub on: u8,
+    pub brightness: u8,
+    pub temperature: u16,
+}
+
+/// The fields to change on one light of a device. Fields left as `None` are not sent, and a light
+/// with nothing to change is sent as an empty object so the others keep their position.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct LightUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<u16>,
+}
+
+/// What a device says about itself, from `/elgato/accessory-info`. Fields a device leaves out
+/// are left empty.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AccessoryInfo {
+    pub product_name: String,
+    pub hardware_board_type: u32,
+    pub firmware_build_number: u32,
+    pub firmware_version: String,
+    pub serial_number: String,
+    pub display_name: String,
+    pub features: Vec<String>,
+}
+
+/// The device's settings, from `/elgato/lights/settings`. `power_on_temperature` is in the
+/// device's units, see [`Temperature::from_device`].
+///
+/// Fields this client doesn't know about are kept in `other`, so they're sent back unchanged.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Settings {
+    pub power_on_behavior: u8,
+    pub power_on_brightness: u8,
+    pub power_on_temperature: u16,
+    pub switch_on_duration_ms: u32,
+    pub switch_off_duration_ms: u32,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+impl AccessoryInfo {
+    /// The display name, or the product name when the device hasn't been given one.
+    pub fn name(&self) -> &str {
+        if self.display_name.is_empty() {
+            &self.product_name
+        } else {
+            &self.display_name
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusUpdate<'a> {
+    number_of_lights: usize,
+    lights: &'a [LightUpdate],
+}
+
+/// A client for the HTTP API of an Elgato light.
+///
+/// Changes apply to every light on the device unless one has been picked with [`KeyLight::select`].
+#[derive(Debug, Clone)]
+pub struct KeyLight {
+    base_url: String,
+    /// The address of a light reached through [`SCOPED_HOST`], for errors to name instead.
+    scoped: Option<SocketAddr>,
+    number_of_lights: usize,
+    index: Option<usize>,
+    fade: Option<Fade>,
+    options: RequestOptions,
+    client: reqwest::Client,
+}
+
+impl KeyLight {
+    /// Connects to the light at `addr`, reading its status to check that it's there.
+    ///
+    /// Every request, including this first one, is retried with `options` when the light doesn't
+    /// answer.
+    pub async fn new_from_ip(addr: SocketAddr, options: RequestOptions) -> Result<KeyLight, Error> {
+        let mut client = reqwest::Client::builder();
+        // URLs can't hold the zone of a link-local IPv6 address, so the light is reached through
+        // a made-up hostname that resolves to the whole address instead.
+        let scoped = match addr {
+            SocketAddr::V6(v6) if v6.scope_id() != 0 => Some(addr),
+            _ => None,
+        };
+        let host = match scoped {
+            Some(addr) => {
+                client = client.resolve(SCOPED_HOST, addr);
+                format!("{}:{}", SCOPED_HOST, addr.port())
+            }
+            None => addr.to_string(),
+        };
+        let client = client
+            .build()
+            .map_err(|e| Error::Other(format!("Unable to create the HTTP client: {}", e)))?;
+        let mut keylight = KeyLight {
+            base_url: format!("http://{}/elgato", host),
+            scoped,
+            number_of_lights: 0,
+            index: None,
+            fade: None,
+            options,
+            client,
+        };
+
+        keylight.number_of_lights = keylight.get().await?.lights.len();
+        Ok(keylight)
+    }
+
+    /// Limits changes to the light at `index` on the device, or to every light when `None`.
+    pub fn select(&mut self, index: Option<usize>) -> Result<(), Error> {
+        if let Some(index) = index {
+            if index >= self.number_of_lights {
+                return Err(Error::InvalidInput(format!(
+                    "Light index {} is out of range, the device has {} light(s)",
+                    index, self.number_of_lights
+                )));
+            }
+        }
+
+        self.index = index;
+        Ok(())
+    }
+
+    /// Makes changes fade in over time instead of happening at once.
+    pub fn set_fade(&mut self, fade: Option<Fade>) {
+        self.fade = fade;
+    }
+
+    /// Changes how long to wait for the light to answer, and how many times to try again.
+    pub fn set_options(&mut self, options: RequestOptions) {
+        self.options = options;
+    }
+
+    pub fn number_of_lights(&self) -> usize {
+        self.number_of_lights
+    }
+
+    pub fn is_selected(&self, index: usize) -> bool {
+        self.index.is_none_or(|selected| selected == index)
+    }
+
+    pub async fn get(&self) -> Result<Status, Error> {
+        self.get_json("lights").await
+    }
+
+    pub async fn accessory_info(&self) -> Result<AccessoryInfo, Error> {
+        self.get_json("accessory-info").await
+    }
+
+    /// Sets the name the device shows in the Elgato apps.
+    pub async fn set_display_name(&self, name: &str) -> Result<(), Error> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct DisplayName<'a> {
+            display_name: &'a str,
+        }
+
+        self.put_json("accessory-info", &DisplayName { display_name: name })
+            .await
+    }
+
+    /// Makes the device flash so it can be picked out from the others.
+    ///
+    /// Devices without an identify endpoint have their selected lights pulsed between full and
+    /// low brightness instead, and are then put back how they were.
+    pub async fn identify(&self) -> Result<(), Error> {
+        match self.post("identify").await {
+            Err(Error::Http { status, .. })
+                if status == StatusCode::NOT_FOUND || status == StatusCode::METHOD_NOT_ALLOWED =>
+            {
+                self.pulse().await
+            }
+            result => result,
+        }
+    }
+
+    async fn pulse(&self) -> Result<(), Error> {
+        let status = self.get().await?;
+        let pulse = |brightness| {
+            self.selected(LightUpdate {
+                on: Some(1),
+                brightness: Some(brightness),
+                ..Default::default()
+            })
+        };
+
+        let mut result = Ok(());
+        for _ in 0..IDENTIFY_PULSES {
+            result = self.send(&pulse(100)).await;
+            if result.is_err() {
+                break;
+            }
+            time::sleep(IDENTIFY_PULSE).await;
+            result = self.send(&pulse(3)).await;
+            if result.is_err() {
+                break;
+            }
+            time::sleep(IDENTIFY_PULSE).await;
+        }
+
+        // Put the lights back even when a pulse failed, so they're not left flashing bright.
+        let restore: Vec<LightUpdate> = status
+            .lights
+            .iter()
+            .enumerate()
+            .map(|(index, light)| {
+                if self.is_selected(index) {
+                    LightUpdate {
+                        on: Some(light.on),
+                        brightness: Some(light.brightness),
+                        temperature: Some(light.temperature),
+                    }
+                } else {
+                    LightUpdate::default()
+                }
+            })
+            .collect();
+        let restored = self.send(&restore).await;
+
+        result.and(restored)
+    }
+
+    pub async fn settings(&self) -> Result<Settings, Error> {
+        self.get_json("lights/settings").await
+    }
+
+    pub async fn set_settings(&self, settings: &Settings) -> Result<(), Error> {
+        self.put_json("lights/settings", settings).await
+    }
+
+    pub async fn set_power(&self, on: bool) -> Result<(), Error> {
+        self.put(LightUpdate {
+            on: Some(on as u8),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Sets the power, brightness and temperature in a single request, so the light doesn't pass
+    /// through in-between states. The brightness is in percent, clamped to 100.
+    pub async fn set_state(
+        &self,
+        on: bool,
+        brightness: u8,
+        temperature: Temperature,
+    ) -> Result<(), Error> {
+        self.put(LightUpdate {
+            on: Some(on as u8),
+            brightness: Some(brightness.min(100)),
+            temperature: Some(temperature.to_device()),
+        })
+        .await
+    }
+
+    /// Reads the state of the selected lights and changes each of them based on it.
+    pub async fn update<F>(&self, change: F) -> Result<(), Error>
+    where
+        F: Fn(&Light) -> LightUpdate,
+    {
+        let status = self.get().await?;
+        let mut lights = Vec::with_capacity(status.lights.len());
+        for (index, light) in status.lights.iter().enumerate() {
+            if self.is_selected(index) {
+                lights.push(change(light));
+            } else {
+                lights.push(LightUpdate::default());
+            }
+        }
+
+        self.apply(&lights, Some(status)).await
+    }
+
+    /// Sends the same update to each selected light.
+    pub async fn put(&self, update: LightUpdate) -> Result<(), Error> {
+        self.set_lights(&self.selected(update)).await
+    }
+
+    /// `update` for each selected light, and no change for the others.
+    fn selected(&self, update: LightUpdate) -> Vec<LightUpdate> {
+        (0..self.number_of_lights)
+            .map(|index| {
+                if self.is_selected(index) {
+                    update.clone()
+                } else {
+                    LightUpdate::default()
+                }
+            })
+            .collect()
+    }
+
+    /// Sends an update for each light on the device, in order.
+    pub async fn set_lights(&self, lights: &[LightUpdate]) -> Result<(), Error> {
+        self.apply(lights, None).await
+    }
+
+    /// Sends the update straight away, or fades to it when a fade is set. `status` is the
+    /// current state of the device if it has already been read.
+    async fn apply(&self, lights: &[LightUpdate], status: Option<Status>) -> Result<(), Error> {
+        let Some(fade) = &self.fade else {
+            return self.send(lights).await;
+        };
+        let status = match status {
+            Some(status) => status,
+            None => self.get().await?,
+        };
+
+        let mut interval = time::interval(fade::STEP);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval.tick().await;
+        for frame in fade.frames(&status.lights, lights) {
+            interval.tick().await;
+            self.send(&frame).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn send(&self, lights: &[LightUpdate]) -> Result<(), Error> {
+        let body = StatusUpdate {
+            number_of_lights: lights.len(),
+            lights,
+        };
+
+        self.put_json("lights", &body).await
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        self.with_retries(|| async {
+            let response = self
+                .client
+                .get(self.url(path))
+                .timeout(self.options.timeout)
+                .send()
+                .await?;
+            Ok(response.error_for_status()?.json().await?)
+        })
+        .await
+    }
+
+    async fn put_json<T: Serialize>(&self, path: &str, body: &T) -> Result<(), Error> {
+        self.with_retries(|| async {
+            self.client
+                .put(self.url(path))
+                .timeout(self.options.timeout)
+                .json(body)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn post(&self, path: &str) -> Result<(), Error> {
+        self.with_retries(|| async {
+            self.client
+                .post(self.url(path))
+                .timeout(self.options.timeout)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+        .await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    /// Runs `request`, trying again with exponential backoff while the light doesn't answer.
+    async fn with_retries<T, F, R>(&self, request: F) -> Result<T, Error>
+    where
+        F: Fn() -> R,
+        R: Future<Output = Result<T, Error>>,
+    {
+        let mut retry = 0;
+        loop {
+            match request().await {
+                Err(e) if e.is_retryable() && retry < self.options.retries => {
+                    retry += 1;
+                    time::sleep(self.options.backoff(retry)).await;
+                }
+                Err(e) => {
+                    let e = e.with_attempts(retry + 1);
+                    return Err(match self.scoped {
+                        Some(addr) => e.with_address(addr.to_string()),
+                        None => e,
+                    });
+                }
+                Ok(output) => return Ok(output),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::unused_port;
+
+    #[test]
+    fn backoff_doubles_with_each_retry() {
+        let options = RequestOptions::default();
+        assert_eq!(options.backoff(1), Duration::from_millis(250));
+        assert_eq!(options.backoff(2), Duration::from_millis(500));
+        assert_eq!(options.backoff(3), Duration::from_millis(1000));
+        assert_eq!(options.backoff(5), Duration::from_secs(4));
+        assert_eq!(options.backoff(6), Duration::from_secs(4));
+        assert_eq!(options.backoff(100), Duration::from_secs(4));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_retries() {
+        let port = unused_port();
+        let keylight = KeyLight {
+            base_url: format!("http://127.0.0.1:{}/elgato", port),
+            scoped: None,
+            number_of_lights: 1,
+            index: None,
+            fade: None,
+            options: RequestOptions {
+                timeout: Duration::from_secs(1),
+                retries: 2,
+            },
+            client: reqwest::Client::new(),
+        };
+
+        let error = keylight.get().await.unwrap_err();
+        assert!(matches!(error, Error::Unreachable { attempts: 3, .. }));
+        assert!(error.to_string().contains("after 3 attempts"));
+    }
+}
@@ -0,0 +1,33 @@
+//! Control Elgato Key Lights, Ring Lights and other Elgato lights over their HTTP API.
+//!
+//! [`LightController`] turns lights on and off and changes their brightness and temperature.
+//! [`keylight::KeyLight`] is the lower level client it's built on, which also reaches the rest of
+//! the device's API.
+//!
+//! [`Error`], [`controller::Operation`] and [`controller::Outcome`] are non-exhaustive, so later
+//! versions can add to them without breaking code that matches on them.
+
+pub mod address;
+pub mod brightness;
+pub mod config;
+pub mod connector;
+pub mod controller;
+#[cfg(unix)]
+pub mod daemon;
+pub mod discovery;
+pub mod error;
+pub mod fade;
+pub mod keylight;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod mqtt;
+pub mod output;
+pub mod scene;
+pub mod server;
+pub mod settings;
+pub mod temperature;
+
+pub use address::Address;
+pub use controller::{LightController, LightState, LightTarget};
+pub use error::Error;
+pub use keylight::RequestOptions;
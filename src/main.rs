@@ -1,135 +1,1227 @@
-use elgato_keylight::KeyLight;
-use std::error::Error;
-use std::net::Ipv4Addr;
-use std::str::FromStr;
+use elgato_light::brightness::{parse_brightness, BrightnessChange};
+use elgato_light::config::{self, Config, LightConfig};
+use elgato_light::connector::Connector;
+use elgato_light::controller::{Operation, Outcome};
+#[cfg(unix)]
+use elgato_light::daemon::{self, Daemon};
+use elgato_light::fade::{parse_duration, Easing, Fade};
+use elgato_light::mqtt::{self, BridgeOptions, Broker};
+use elgato_light::output::{self, DeviceInfo, LightSettings, LightStatus, OutputFormat};
+use elgato_light::scene::{self, SceneLight};
+use elgato_light::server::Server;
+use elgato_light::settings::{parse_switch_duration, PowerOnBehavior, SettingsChange};
+use elgato_light::temperature::{Temperature, TemperatureChange};
+use elgato_light::{discovery, keylight};
+use elgato_light::{Address, Error, LightTarget, RequestOptions};
+use futures::future::join_all;
+use std::collections::HashSet;
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+#[cfg(unix)]
+use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
 
-const DEFAULT_IP_ADDRESS: &str = "192.168.0.25";
+/// Percentage points `brightness --up` and `--down` change the brightness by.
+const DEFAULT_BRIGHTNESS_STEP: u8 = 10;
 
 #[derive(StructOpt, Debug)]
 #[structopt(
     name = "elgato light",
     about = "A command line interface for controlling an Elgato light by its IP address"
 )]
+struct Cli {
+    #[structopt(
+        short = "c",
+        long = "config",
+        env = config::CONFIG_ENV_VAR,
+        global = true,
+        parse(from_os_str),
+        help = "Path to the config file (defaults to ~/.config/elgato-light/config.toml)"
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        long = "timeout",
+        global = true,
+        parse(try_from_str = parse_duration),
+        help = "How long to wait for a light to answer, such as 500ms or 2s (defaults to 5s)"
+    )]
+    timeout: Option<Duration>,
+
+    #[structopt(
+        long = "retries",
+        global = true,
+        help = "How many times to try again when a light doesn't answer (defaults to 2)"
+    )]
+    retries: Option<u32>,
+
+    #[structopt(
+        long = "socket",
+        env = "ELGATO_LIGHT_SOCKET",
+        global = true,
+        parse(from_os_str),
+        help = "Path to the daemon's socket (defaults to $XDG_RUNTIME_DIR/elgato-light.sock, or ~/.cache/elgato-light/daemon.sock)"
+    )]
+    socket: Option<PathBuf>,
+
+    #[structopt(
+        long = "no-daemon",
+        global = true,
+        help = "Control the lights directly even when the daemon is running"
+    )]
+    no_daemon: bool,
+
+    #[structopt(subcommand)]
+    command: ElgatoLight,
+}
+
+#[derive(StructOpt, Debug)]
+struct Target {
+    #[structopt(
+        short = "i",
+        long = "ip-address",
+        alias = "address",
+        number_of_values = 1,
+        help = "Specify the IP address or hostname of the Elgato Light, optionally with a port such as keylight.local:9123. Repeat to control several lights"
+    )]
+    ip_address: Vec<String>,
+
+    #[structopt(
+        short = "l",
+        long = "light",
+        number_of_values = 1,
+        help = "Specify the light by its name in the config file or from discovery. Repeat to control several lights"
+    )]
+    light: Vec<String>,
+
+    #[structopt(
+        short = "a",
+        long = "all",
+        conflicts_with_all = &["ip-address", "light"],
+        help = "Control every light in the config file"
+    )]
+    all: bool,
+
+    #[structopt(
+        long = "index",
+        help = "Only control the light at this position on the device, starting at 0. Defaults to every light on the device"
+    )]
+    index: Option<usize>,
+}
+
+#[derive(StructOpt, Debug)]
+struct FadeArgs {
+    #[structopt(
+        long = "fade",
+        parse(try_from_str = parse_duration),
+        help = "Fade to the new state over a duration, such as 500ms or 2s"
+    )]
+    fade: Option<Duration>,
+
+    #[structopt(
+        long = "easing",
+        default_value = "ease-in-out",
+        possible_values = &["linear", "ease-in", "ease-out", "ease-in-out"],
+        help = "Easing curve for --fade"
+    )]
+    easing: Easing,
+}
+
+impl FadeArgs {
+    fn fade(&self) -> Option<Fade> {
+        self.fade.map(|duration| Fade {
+            duration,
+            easing: self.easing,
+        })
+    }
+}
+
+#[derive(StructOpt, Debug)]
 enum ElgatoLight {
     #[structopt(about = "Turns the light on with specified brightness and temperature")]
     On {
         #[structopt(
             short = "b",
             long = "brightness",
-            default_value = "10",
+            parse(try_from_str = parse_brightness),
             help = "Set the brightness level (0-100)"
         )]
-        brightness: u8,
+        brightness: Option<u8>,
 
         #[structopt(
             short = "t",
             long = "temperature",
-            default_value = "3000",
-            help = "Set the color temperature (2900-7000)"
+            help = "Set the color temperature in Kelvin (2900-7000)"
         )]
-        temperature: u32,
+        temperature: Option<Temperature>,
+
+        #[structopt(flatten)]
+        fade: FadeArgs,
 
-        #[structopt(short = "i", long = "ip-address", default_value = DEFAULT_IP_ADDRESS, help = "Specify the IP address of the Elgato Light")]
-        ip_address: String,
+        #[structopt(flatten)]
+        target: Target,
     },
     #[structopt(about = "Turns the light off")]
     Off {
-        #[structopt(short = "i", long = "ip-address", default_value = DEFAULT_IP_ADDRESS, help = "Specify the IP address of the Elgato Light")]
-        ip_address: String,
+        #[structopt(flatten)]
+        fade: FadeArgs,
+
+        #[structopt(flatten)]
+        target: Target,
+    },
+    #[structopt(
+        about = "Turns the light on if it's off and off if it's on, keeping its brightness and temperature"
+    )]
+    Toggle {
+        #[structopt(flatten)]
+        fade: FadeArgs,
+
+        #[structopt(flatten)]
+        target: Target,
     },
     #[structopt(
         about = "Changes the brightness of the light. Use -100 to 100. Use -- to pass negative arguments."
     )]
     Brightness {
-        #[structopt(help = "Change the brightness level (-100 to 100)")]
-        brightness: i8,
+        #[structopt(
+            required_unless_one = &["set", "up", "down"],
+            conflicts_with_all = &["set", "up", "down"],
+            help = "Change the brightness level (-100 to 100)"
+        )]
+        brightness: Option<i32>,
 
-        #[structopt(short = "i", long = "ip-address", default_value = DEFAULT_IP_ADDRESS, help = "Specify the IP address of the Elgato Light")]
-        ip_address: String,
+        #[structopt(
+            short = "s",
+            long = "set",
+            parse(try_from_str = parse_brightness),
+            conflicts_with_all = &["up", "down", "absolute", "relative"],
+            help = "Set the brightness level (0-100)"
+        )]
+        set: Option<u8>,
+
+        #[structopt(
+            long = "up",
+            conflicts_with_all = &["down", "absolute", "relative"],
+            help = "Raise the brightness by the step"
+        )]
+        up: bool,
+
+        #[structopt(
+            long = "down",
+            conflicts_with_all = &["absolute", "relative"],
+            help = "Lower the brightness by the step"
+        )]
+        down: bool,
+
+        #[structopt(
+            long = "step",
+            conflicts_with_all = &["absolute", "relative"],
+            parse(try_from_str = parse_brightness),
+            help = "Percentage points to change the brightness by with --up and --down (defaults to 10)"
+        )]
+        step: Option<u8>,
+
+        #[structopt(
+            long = "absolute",
+            conflicts_with = "relative",
+            help = "Set the brightness to the value (0-100) instead of changing it"
+        )]
+        absolute: bool,
+
+        #[structopt(
+            long = "relative",
+            help = "Change the brightness by the value (the default)"
+        )]
+        relative: bool,
+
+        #[structopt(flatten)]
+        fade: FadeArgs,
+
+        #[structopt(flatten)]
+        target: Target,
     },
-    #[structopt(about = "Sets the temperature of the light")]
+    #[structopt(
+        about = "Sets the temperature of the light. Use +200 or -200 to change it. Use -- to pass negative arguments."
+    )]
     Temperature {
-        #[structopt(help = "Set the color temperature (2900-7000)")]
-        temperature: u32,
+        #[structopt(
+            help = "Set the color temperature in Kelvin (2900-7000), or change it with a + or - sign"
+        )]
+        temperature: TemperatureChange,
+
+        #[structopt(
+            long = "absolute",
+            conflicts_with = "relative",
+            help = "Set the temperature to the value even when it has a sign"
+        )]
+        absolute: bool,
 
-        #[structopt(short = "i", long = "ip-address", default_value = DEFAULT_IP_ADDRESS, help = "Specify the IP address of the Elgato Light")]
-        ip_address: String,
+        #[structopt(
+            long = "relative",
+            help = "Change the temperature by the value even when it has no sign"
+        )]
+        relative: bool,
+
+        #[structopt(flatten)]
+        fade: FadeArgs,
+
+        #[structopt(flatten)]
+        target: Target,
     },
     #[structopt(about = "Gets the status of the light")]
     Status {
-        #[structopt(short = "i", long = "ip-address", default_value = DEFAULT_IP_ADDRESS, help = "Specify the IP address of the Elgato Light")]
-        ip_address: String,
+        #[structopt(
+            short = "o",
+            long = "output",
+            default_value = "plain",
+            possible_values = &["json", "yaml", "table", "plain"],
+            help = "Output format"
+        )]
+        output: OutputFormat,
+
+        #[structopt(flatten)]
+        target: Target,
+    },
+    #[structopt(
+        about = "Shows the product name, serial number, firmware version and other details of the device"
+    )]
+    Info {
+        #[structopt(
+            short = "o",
+            long = "output",
+            default_value = "plain",
+            possible_values = &["json", "yaml", "table", "plain"],
+            help = "Output format"
+        )]
+        output: OutputFormat,
+
+        #[structopt(flatten)]
+        target: Target,
+    },
+    #[structopt(about = "Flashes the light so you can tell which one it is")]
+    Identify {
+        #[structopt(flatten)]
+        target: Target,
+    },
+    #[structopt(about = "Changes the name the device shows in the Elgato apps")]
+    Rename {
+        #[structopt(help = "New name for the device")]
+        name: String,
+
+        #[structopt(flatten)]
+        target: Target,
+    },
+    #[structopt(about = "Discovers Elgato lights on the local network")]
+    Discover {
+        #[structopt(
+            short = "t",
+            long = "duration",
+            parse(try_from_str = parse_duration),
+            help = "How long to browse for lights, such as 500ms or 10s (defaults to 3s)"
+        )]
+        duration: Option<Duration>,
+    },
+    #[structopt(
+        about = "Keeps the lights connected so the other subcommands, which use it while it runs, respond faster"
+    )]
+    Daemon,
+    #[structopt(about = "Serves an HTTP API for controlling the lights from other programs")]
+    Serve {
+        #[structopt(
+            short = "b",
+            long = "bind",
+            default_value = "127.0.0.1:8080",
+            help = "Address and port to listen on. Use 0.0.0.0:8080 to accept requests from other machines"
+        )]
+        bind: SocketAddr,
+
+        #[structopt(
+            long = "token",
+            env = "ELGATO_LIGHT_TOKEN",
+            hide_env_values = true,
+            help = "Require requests to send this token as \"Authorization: Bearer <token>\""
+        )]
+        token: Option<String>,
+    },
+    #[structopt(
+        about = "Bridges the named lights to an MQTT broker, with Home Assistant discovery"
+    )]
+    Mqtt {
+        #[structopt(
+            long = "broker",
+            default_value = "localhost",
+            parse(try_from_str = mqtt::parse_broker),
+            help = "Broker to connect to, such as mqtt://192.168.1.10:1883"
+        )]
+        broker: Broker,
+
+        #[structopt(long = "username", help = "Username to log in to the broker with")]
+        username: Option<String>,
+
+        #[structopt(
+            long = "password",
+            env = "ELGATO_LIGHT_MQTT_PASSWORD",
+            hide_env_values = true,
+            help = "Password to log in to the broker with"
+        )]
+        password: Option<String>,
+
+        #[structopt(
+            long = "prefix",
+            default_value = mqtt::DEFAULT_PREFIX,
+            help = "Start of the topics for the lights' state and commands"
+        )]
+        prefix: String,
+
+        #[structopt(
+            long = "discovery-prefix",
+            default_value = mqtt::DEFAULT_DISCOVERY_PREFIX,
+            help = "Topic prefix Home Assistant listens on for discovery"
+        )]
+        discovery_prefix: String,
+
+        #[structopt(long = "no-discovery", help = "Don't publish Home Assistant discovery")]
+        no_discovery: bool,
+
+        #[structopt(
+            long = "interval",
+            default_value = "10s",
+            parse(try_from_str = parse_duration),
+            help = "How often to read the lights, to publish changes made elsewhere"
+        )]
+        interval: Duration,
     },
+    #[structopt(about = "Manages the named lights in the config file")]
+    Light(LightCommand),
+    #[structopt(about = "Saves the state of lights as a scene and applies it later")]
+    Scene(SceneCommand),
+    #[structopt(
+        about = "Reads and changes the device settings, such as what the light does when it gets power"
+    )]
+    Settings(SettingsCommand),
 }
 
-impl ElgatoLight {
-    fn ip_address(&self) -> Result<Ipv4Addr, Box<dyn Error>> {
-        let ip_str = match self {
-            ElgatoLight::On { ip_address, .. }
-            | ElgatoLight::Off { ip_address }
-            | ElgatoLight::Brightness { ip_address, .. }
-            | ElgatoLight::Temperature { ip_address, .. }
-            | ElgatoLight::Status { ip_address } => ip_address,
-        };
+#[derive(StructOpt, Debug)]
+enum LightCommand {
+    #[structopt(about = "Adds a named light, replacing any light with the same name")]
+    Add {
+        #[structopt(help = "Name to refer to the light by, such as desk-key")]
+        name: String,
+
+        #[structopt(help = "IP address or hostname of the light, optionally with a port")]
+        ip_address: Address,
 
-        Ipv4Addr::from_str(ip_str).map_err(|_| "Invalid IP address format".into())
+        #[structopt(
+            short = "b",
+            long = "brightness",
+            parse(try_from_str = parse_brightness),
+            help = "Brightness to turn this light on with (0-100)"
+        )]
+        brightness: Option<u8>,
+
+        #[structopt(
+            short = "t",
+            long = "temperature",
+            help = "Temperature in Kelvin to turn this light on with (2900-7000)"
+        )]
+        temperature: Option<Temperature>,
+
+        #[structopt(long = "default", help = "Make this the default light")]
+        default: bool,
+    },
+    #[structopt(about = "Removes a named light")]
+    Remove {
+        #[structopt(help = "Name of the light")]
+        name: String,
+    },
+    #[structopt(about = "Lists the named lights")]
+    List,
+}
+
+#[derive(StructOpt, Debug)]
+enum SceneCommand {
+    #[structopt(
+        about = "Saves the current state of the lights, replacing any scene with the same name"
+    )]
+    Save {
+        #[structopt(help = "Name of the scene, such as recording")]
+        name: String,
+
+        #[structopt(flatten)]
+        target: Target,
+    },
+    #[structopt(about = "Restores the lights to a saved scene")]
+    Apply {
+        #[structopt(help = "Name of the scene")]
+        name: String,
+
+        #[structopt(flatten)]
+        fade: FadeArgs,
+    },
+    #[structopt(about = "Removes a saved scene")]
+    Remove {
+        #[structopt(help = "Name of the scene")]
+        name: String,
+    },
+    #[structopt(about = "Lists the saved scenes")]
+    List,
+}
+
+#[derive(StructOpt, Debug)]
+enum SettingsCommand {
+    #[structopt(about = "Shows the device settings")]
+    Get {
+        #[structopt(
+            short = "o",
+            long = "output",
+            default_value = "plain",
+            possible_values = &["json", "yaml", "table", "plain"],
+            help = "Output format"
+        )]
+        output: OutputFormat,
+
+        #[structopt(flatten)]
+        target: Target,
+    },
+    #[structopt(about = "Changes the device settings, keeping any that aren't given")]
+    Set {
+        #[structopt(
+            long = "power-on-behavior",
+            possible_values = &["restore", "default"],
+            help = "Whether the light comes back in its last state after losing power (restore) or on with the power-on brightness and temperature (default)"
+        )]
+        power_on_behavior: Option<PowerOnBehavior>,
+
+        #[structopt(
+            long = "power-on-brightness",
+            parse(try_from_str = parse_brightness),
+            help = "Brightness to come back on with (0-100)"
+        )]
+        power_on_brightness: Option<u8>,
+
+        #[structopt(
+            long = "power-on-temperature",
+            help = "Temperature in Kelvin to come back on with (2900-7000)"
+        )]
+        power_on_temperature: Option<Temperature>,
+
+        #[structopt(
+            long = "switch-on-duration",
+            parse(try_from_str = parse_switch_duration),
+            help = "How long the light takes to turn on, up to 10s, such as 100ms"
+        )]
+        switch_on_duration: Option<Duration>,
+
+        #[structopt(
+            long = "switch-off-duration",
+            parse(try_from_str = parse_switch_duration),
+            help = "How long the light takes to turn off, up to 10s, such as 300ms"
+        )]
+        switch_off_duration: Option<Duration>,
+
+        #[structopt(flatten)]
+        target: Target,
+    },
+}
+
+impl Cli {
+    /// The timeout and retries from the command line, falling back to the config file.
+    fn request_options(&self, config: &Config) -> RequestOptions {
+        RequestOptions {
+            timeout: self
+                .timeout
+                .or(config.timeout)
+                .unwrap_or(keylight::DEFAULT_TIMEOUT),
+            retries: self
+                .retries
+                .or(config.retries)
+                .unwrap_or(keylight::DEFAULT_RETRIES),
+        }
+    }
+
+    /// Connects through the daemon when it's running, unless --no-daemon is given.
+    async fn connector(&self, options: RequestOptions) -> Connector {
+        Connector::new(self.daemon_socket().as_deref(), options).await
     }
 
-    async fn get_keylight(ip_address: Ipv4Addr) -> Result<KeyLight, Box<dyn Error>> {
-        let keylight = KeyLight::new_from_ip("Elgato Light", ip_address, None).await?;
-        Ok(keylight)
+    /// The daemon's socket, unless --no-daemon is given.
+    fn daemon_socket(&self) -> Option<PathBuf> {
+        #[cfg(unix)]
+        return self.socket_path().ok().filter(|_| !self.no_daemon);
+        #[cfg(not(unix))]
+        None
     }
 
-    async fn ensure_light_on(keylight: &mut KeyLight) -> Result<(), Box<dyn Error>> {
-        let status = keylight.get().await?;
-        if status.lights[0].on == 0 {
-            keylight.set_power(true).await?;
+    #[cfg(unix)]
+    fn socket_path(&self) -> Result<PathBuf, Error> {
+        self.socket
+            .clone()
+            .or_else(daemon::socket_path)
+            .ok_or_else(|| {
+                Error::Config(
+                    "Unable to find the daemon's socket. Set HOME or use --socket".to_string(),
+                )
+            })
+    }
+}
+
+impl Target {
+    /// Resolves the targeted lights to a label for reporting and the light to connect to.
+    fn lights(&self, config: &Config) -> Result<Vec<(String, LightTarget)>, Error> {
+        let mut seen = HashSet::new();
+        Ok(self
+            .addresses(config)?
+            .into_iter()
+            .filter(|(_, address)| seen.insert(address.clone()))
+            .map(|(label, address)| {
+                let target = LightTarget {
+                    address,
+                    index: self.index,
+                };
+                (label, target)
+            })
+            .collect())
+    }
+
+    fn addresses(&self, config: &Config) -> Result<Vec<(String, Address)>, Error> {
+        if self.all {
+            if config.lights.is_empty() {
+                return Err(Error::Config(
+                    "There are no lights in the config file".to_string(),
+                ));
+            }
+            return Ok(config
+                .lights
+                .iter()
+                .map(|(name, light)| (name.clone(), light.ip_address.clone()))
+                .collect());
         }
-        Ok(())
+
+        let mut lights = Vec::new();
+        for ip_str in &self.ip_address {
+            let address = ip_str.parse().map_err(Error::InvalidAddress)?;
+            lights.push((ip_str.clone(), address));
+        }
+        for name in &self.light {
+            lights.push((name.clone(), config.resolve(name)?));
+        }
+
+        if lights.is_empty() {
+            let name = config.default_light.as_ref().ok_or_else(|| {
+                Error::InvalidInput(
+                    "No light specified. Use --ip-address, --light, or set default_light in the config file"
+                        .to_string(),
+                )
+            })?;
+            lights.push((name.clone(), config.resolve(name)?));
+        }
+
+        Ok(lights)
     }
+}
 
-    async fn run(&self, mut keylight: KeyLight) -> Result<(), Box<dyn Error>> {
+impl LightCommand {
+    fn run(&self, config_path: Option<&Path>) -> Result<(), Error> {
         match self {
-            ElgatoLight::On {
+            LightCommand::Add {
+                name,
+                ip_address,
                 brightness,
                 temperature,
-                ..
+                default,
             } => {
-                keylight.set_power(true).await?;
-                keylight.set_brightness(*brightness).await?;
-                keylight.set_temperature(*temperature).await?;
+                let light = LightConfig {
+                    ip_address: ip_address.clone(),
+                    brightness: *brightness,
+                    temperature: *temperature,
+                };
+                config::add_light(&Config::path(config_path)?, name, &light, *default)?;
+            }
+            LightCommand::Remove { name } => {
+                if !config::remove_light(&Config::path(config_path)?, name)? {
+                    return Err(Error::InvalidInput(format!("Unknown light \"{}\"", name)));
+                }
+            }
+            LightCommand::List => {
+                let config = Config::load(config_path)?;
+                for (name, light) in &config.lights {
+                    let default = if config.default_light.as_ref() == Some(name) {
+                        " (default)"
+                    } else {
+                        ""
+                    };
+                    println!("{}\t{}{}", name, light.ip_address, default);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SceneCommand {
+    async fn run(
+        &self,
+        config_path: Option<&Path>,
+        config: &Config,
+        connector: &Connector,
+    ) -> Result<(), Error> {
+        let path = scene::path(config_path)?;
+        let mut scenes = scene::load(&path)?;
+
+        match self {
+            SceneCommand::Save { name, target } => {
+                let lights = target.lights(config)?;
+                let tasks = lights
+                    .iter()
+                    .map(|(label, target)| {
+                        (
+                            label.clone(),
+                            SceneCommand::capture(label, target, connector),
+                        )
+                    })
+                    .collect();
+
+                let (scene_lights, result) = run_concurrently(tasks, false).await;
+                result?;
+                scenes.insert(name.clone(), scene_lights);
+                scene::save(&path, &scenes)?;
             }
-            ElgatoLight::Off { .. } => {
-                keylight.set_power(false).await?;
+            SceneCommand::Apply { name, fade } => {
+                let scene = scenes
+                    .get(name)
+                    .ok_or_else(|| Error::InvalidInput(format!("Unknown scene \"{}\"", name)))?;
+                let tasks = scene
+                    .iter()
+                    .map(|scene_light| {
+                        (
+                            scene_light.light.clone(),
+                            scene::restore(scene_light, config, fade.fade(), connector),
+                        )
+                    })
+                    .collect();
+
+                let (_, result) = run_concurrently(tasks, true).await;
+                result?;
             }
-            ElgatoLight::Brightness { brightness, .. } => {
-                ElgatoLight::ensure_light_on(&mut keylight).await?;
-                let status = keylight.get().await?;
-                let current_brightness = status.lights[0].brightness;
-                let new_brightness = ((current_brightness as i8) + *brightness).clamp(0, 100) as u8;
-                keylight.set_brightness(new_brightness).await?;
+            SceneCommand::Remove { name } => {
+                if scenes.remove(name).is_none() {
+                    return Err(Error::InvalidInput(format!("Unknown scene \"{}\"", name)));
+                }
+                scene::save(&path, &scenes)?;
             }
-            ElgatoLight::Temperature { temperature, .. } => {
-                ElgatoLight::ensure_light_on(&mut keylight).await?;
-                keylight.set_temperature(*temperature).await?;
+            SceneCommand::List => {
+                for (name, scene_lights) in &scenes {
+                    let lights: Vec<&str> = scene_lights
+                        .iter()
+                        .map(|scene_light| scene_light.light.as_str())
+                        .collect();
+                    println!("{}\t{}", name, lights.join(", "));
+                }
             }
-            ElgatoLight::Status { .. } => {
-                let status = keylight.get().await?;
-                println!("{:?}", status);
+        }
+
+        Ok(())
+    }
+
+    async fn capture(
+        label: &str,
+        target: &LightTarget,
+        connector: &Connector,
+    ) -> Result<SceneLight, Error> {
+        let Outcome::State { lights, .. } =
+            connector.perform(target, None, Operation::State).await?
+        else {
+            unreachable!("reading the state returns it");
+        };
+
+        Ok(SceneLight {
+            light: label.to_string(),
+            ip_address: target.address.clone(),
+            lights,
+        })
+    }
+}
+
+impl SettingsCommand {
+    async fn run(&self, config: &Config, connector: &Connector) -> Result<(), Error> {
+        match self {
+            SettingsCommand::Get { output, target } => {
+                let tasks = target
+                    .lights(config)?
+                    .into_iter()
+                    .map(|(label, target)| {
+                        (
+                            label.clone(),
+                            SettingsCommand::get(label, target, connector),
+                        )
+                    })
+                    .collect();
+
+                let (settings, result) = run_concurrently(tasks, false).await;
+                if !settings.is_empty() {
+                    print!("{}", output::render(*output, &settings)?);
+                }
+                result?;
+            }
+            SettingsCommand::Set {
+                power_on_behavior,
+                power_on_brightness,
+                power_on_temperature,
+                switch_on_duration,
+                switch_off_duration,
+                target,
+            } => {
+                let change = SettingsChange {
+                    power_on_behavior: *power_on_behavior,
+                    power_on_brightness: *power_on_brightness,
+                    power_on_temperature: *power_on_temperature,
+                    switch_on_duration: *switch_on_duration,
+                    switch_off_duration: *switch_off_duration,
+                };
+                if change.is_empty() {
+                    return Err(Error::InvalidInput(
+                        "Nothing to change. Use --power-on-behavior, --power-on-brightness, --power-on-temperature, --switch-on-duration or --switch-off-duration".to_string(),
+                    ));
+                }
+
+                let tasks = target
+                    .lights(config)?
+                    .into_iter()
+                    .map(|(label, target)| (label, SettingsCommand::set(target, change, connector)))
+                    .collect();
+
+                let (_, result) = run_concurrently(tasks, true).await;
+                result?;
             }
         }
 
         Ok(())
     }
+
+    async fn get(
+        label: String,
+        target: LightTarget,
+        connector: &Connector,
+    ) -> Result<LightSettings, Error> {
+        let Outcome::Settings(settings) = connector
+            .perform(&target, None, Operation::Settings)
+            .await?
+        else {
+            unreachable!("reading the settings returns them");
+        };
+
+        Ok(LightSettings {
+            light: label,
+            power_on_behavior: PowerOnBehavior::from_device(settings.power_on_behavior).to_string(),
+            power_on_brightness: settings.power_on_brightness,
+            power_on_temperature: Temperature::from_device(settings.power_on_temperature).kelvin(),
+            switch_on_duration_ms: settings.switch_on_duration_ms,
+            switch_off_duration_ms: settings.switch_off_duration_ms,
+        })
+    }
+
+    async fn set(
+        target: LightTarget,
+        change: SettingsChange,
+        connector: &Connector,
+    ) -> Result<(), Error> {
+        connector
+            .perform(&target, None, Operation::ChangeSettings(change))
+            .await?;
+        Ok(())
+    }
+}
+
+impl ElgatoLight {
+    fn target(&self) -> Option<&Target> {
+        match self {
+            ElgatoLight::On { target, .. }
+            | ElgatoLight::Off { target, .. }
+            | ElgatoLight::Toggle { target, .. }
+            | ElgatoLight::Brightness { target, .. }
+            | ElgatoLight::Temperature { target, .. }
+            | ElgatoLight::Status { target, .. }
+            | ElgatoLight::Info { target, .. }
+            | ElgatoLight::Rename { target, .. }
+            | ElgatoLight::Identify { target } => Some(target),
+            ElgatoLight::Discover { .. }
+            | ElgatoLight::Daemon
+            | ElgatoLight::Serve { .. }
+            | ElgatoLight::Mqtt { .. }
+            | ElgatoLight::Light(_)
+            | ElgatoLight::Scene(_)
+            | ElgatoLight::Settings(_) => None,
+        }
+    }
+
+    fn fade(&self) -> Option<Fade> {
+        match self {
+            ElgatoLight::On { fade, .. }
+            | ElgatoLight::Off { fade, .. }
+            | ElgatoLight::Toggle { fade, .. }
+            | ElgatoLight::Brightness { fade, .. }
+            | ElgatoLight::Temperature { fade, .. } => fade.fade(),
+            _ => None,
+        }
+    }
+
+    fn lights(&self, config: &Config) -> Result<Vec<(String, LightTarget)>, Error> {
+        self.target()
+            .ok_or_else(|| Error::InvalidInput("This command does not target a light".to_string()))?
+            .lights(config)
+    }
+
+    async fn info(
+        label: &str,
+        target: &LightTarget,
+        connector: &Connector,
+    ) -> Result<DeviceInfo, Error> {
+        let Outcome::Info(info) = connector.perform(target, None, Operation::Info).await? else {
+            unreachable!("reading the info returns it");
+        };
+
+        Ok(DeviceInfo {
+            light: label.to_string(),
+            product_name: info.product_name,
+            display_name: info.display_name,
+            serial_number: info.serial_number,
+            firmware_version: info.firmware_version,
+            firmware_build_number: info.firmware_build_number,
+            hardware_board_type: info.hardware_board_type,
+            features: info.features,
+        })
+    }
+
+    async fn discover(duration: Duration) -> Result<(), Error> {
+        let lights = discovery::discover(discovery::SERVICE_TYPE, duration).await?;
+
+        // Keep the lights from the last discovery when none answered this time, so a brief
+        // network problem doesn't forget them.
+        if lights.is_empty() {
+            println!("No lights found");
+            return Ok(());
+        }
+        for light in &lights {
+            println!(
+                "{}\t{}\t{}\t{}",
+                light.name,
+                light.ip_address,
+                light.port,
+                light.serial_number.as_deref().unwrap_or("unknown")
+            );
+        }
+
+        // The lights have been listed either way, so only warn when they can't be remembered.
+        if let Err(e) = discovery::save_cache(&lights) {
+            eprintln!("Warning: {}", e);
+        }
+
+        Ok(())
+    }
+
+    /// Runs the command against the targeted light, returning the status of its lights for the
+    /// status command.
+    async fn run(
+        &self,
+        label: &str,
+        target: &LightTarget,
+        config: &Config,
+        connector: &Connector,
+    ) -> Result<Vec<LightStatus>, Error> {
+        let operation = match self {
+            ElgatoLight::On {
+                brightness,
+                temperature,
+                ..
+            } => Operation::SetState {
+                on: true,
+                brightness: brightness.unwrap_or_else(|| config.brightness(&target.address)),
+                temperature: temperature.unwrap_or_else(|| config.temperature(&target.address)),
+            },
+            ElgatoLight::Off { .. } => Operation::SetPower(false),
+            ElgatoLight::Toggle { .. } => Operation::Toggle,
+            ElgatoLight::Brightness {
+                brightness,
+                set,
+                up,
+                down,
+                step,
+                absolute,
+                relative,
+                ..
+            } => {
+                let step = step.unwrap_or(DEFAULT_BRIGHTNESS_STEP);
+                let change = match (set, brightness) {
+                    (Some(set), _) => BrightnessChange::Set(*set),
+                    (None, Some(brightness)) if *absolute && !*relative => {
+                        BrightnessChange::set(*brightness).map_err(Error::InvalidInput)?
+                    }
+                    (None, Some(brightness)) => BrightnessChange::By(*brightness),
+                    (None, None) if *up => BrightnessChange::By(i32::from(step)),
+                    (None, None) if *down => BrightnessChange::By(-i32::from(step)),
+                    (None, None) => unreachable!("a brightness, --set, --up or --down is required"),
+                };
+                Operation::AdjustBrightness(change)
+            }
+            ElgatoLight::Temperature {
+                temperature,
+                absolute,
+                relative,
+                ..
+            } => Operation::AdjustTemperature(
+                temperature
+                    .adjustment(*absolute, *relative)
+                    .map_err(Error::InvalidInput)?,
+            ),
+            ElgatoLight::Identify { .. } => Operation::Identify,
+            ElgatoLight::Rename { name, .. } => Operation::Rename(name.clone()),
+            ElgatoLight::Status { .. } => Operation::State,
+            ElgatoLight::Info { .. } => unreachable!("runs separately to return the info"),
+            ElgatoLight::Discover { .. }
+            | ElgatoLight::Daemon
+            | ElgatoLight::Serve { .. }
+            | ElgatoLight::Mqtt { .. }
+            | ElgatoLight::Light(_)
+            | ElgatoLight::Scene(_)
+            | ElgatoLight::Settings(_) => unreachable!("runs without a light"),
+        };
+
+        let outcome = connector.perform(target, self.fade(), operation).await?;
+        Ok(LightStatus::from_outcome(label, outcome))
+    }
+}
+
+/// Runs a task for each light concurrently. When there are several lights, each one's failure is
+/// printed, along with its success if `report_ok` is set.
+///
+/// Returns the outputs of the lights that succeeded, so they can still be shown, and an error if
+/// any light failed.
+async fn run_concurrently<T, F>(
+    tasks: Vec<(String, F)>,
+    report_ok: bool,
+) -> (Vec<T>, Result<(), Error>)
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    let (labels, futures): (Vec<_>, Vec<_>) = tasks.into_iter().unzip();
+    let results = join_all(futures).await;
+
+    let mut outputs = Vec::new();
+    let mut failures = 0;
+    for (label, result) in labels.iter().zip(results) {
+        match result {
+            Ok(output) => {
+                if labels.len() > 1 && report_ok {
+                    println!("{}: ok", label);
+                }
+                outputs.push(output);
+            }
+            Err(e) if labels.len() == 1 => return (outputs, Err(e)),
+            Err(e) => {
+                failures += 1;
+                eprintln!("{}: {}", label, e);
+            }
+        }
+    }
+
+    if failures > 0 {
+        let failed = Error::LightsFailed {
+            failed: failures,
+            total: labels.len(),
+        };
+        return (outputs, Err(failed));
+    }
+
+    (outputs, Ok(()))
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let args = ElgatoLight::from_args();
-    let ip_address = args.ip_address()?;
-    let keylight = ElgatoLight::get_keylight(ip_address).await?;
-    args.run(keylight).await?;
+async fn main() -> ExitCode {
+    match run(Cli::from_args()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Error> {
+    let args = &cli.command;
+
+    match args {
+        ElgatoLight::Discover { duration } => {
+            let duration = duration.unwrap_or(discovery::DEFAULT_DURATION);
+            return ElgatoLight::discover(duration).await;
+        }
+        ElgatoLight::Light(command) => return command.run(cli.config.as_deref()),
+        _ => {}
+    }
+
+    let config = Config::load(cli.config.as_deref())?;
+    let options = cli.request_options(&config);
+    if let ElgatoLight::Daemon = args {
+        return run_daemon(&cli, &config, options).await;
+    }
+
+    if let ElgatoLight::Mqtt {
+        broker,
+        username,
+        password,
+        prefix,
+        discovery_prefix,
+        no_discovery,
+        interval,
+    } = args
+    {
+        let bridge = BridgeOptions {
+            broker: broker.clone(),
+            username: username.clone(),
+            password: password.clone(),
+            prefix: prefix.trim_end_matches('/').to_string(),
+            discovery_prefix: (!no_discovery)
+                .then(|| discovery_prefix.trim_end_matches('/').to_string()),
+            interval: *interval,
+        };
+        return run_mqtt(bridge, &config, options).await;
+    }
+
+    if let ElgatoLight::Serve { bind, token } = args {
+        let socket = cli.daemon_socket();
+        return serve(cli.config.clone(), socket, options, *bind, token.clone()).await;
+    }
+
+    let connector = cli.connector(options).await;
+    if let ElgatoLight::Scene(command) = args {
+        return command
+            .run(cli.config.as_deref(), &config, &connector)
+            .await;
+    }
+    if let ElgatoLight::Settings(command) = args {
+        return command.run(&config, &connector).await;
+    }
+
+    let lights = args.lights(&config)?;
+    if let ElgatoLight::Rename { name, .. } = args {
+        if name.trim().is_empty() {
+            return Err(Error::InvalidInput("The name can't be empty".to_string()));
+        }
+        if lights.len() > 1 {
+            return Err(Error::InvalidInput(
+                "Rename one light at a time, so they don't end up with the same name".to_string(),
+            ));
+        }
+    }
+
+    if let ElgatoLight::Info { output, .. } = args {
+        let tasks = lights
+            .iter()
+            .map(|(label, target)| (label.clone(), ElgatoLight::info(label, target, &connector)))
+            .collect();
+        let (infos, result) = run_concurrently(tasks, false).await;
+        if !infos.is_empty() {
+            print!("{}", output::render(*output, &infos)?);
+        }
+        return result;
+    }
+
+    let tasks = lights
+        .iter()
+        .map(|(label, target)| (label.clone(), args.run(label, target, &config, &connector)))
+        .collect();
+    let report_ok = !matches!(args, ElgatoLight::Status { .. });
+    let (statuses, result) = run_concurrently(tasks, report_ok).await;
+
+    // Show the lights that answered even when others didn't.
+    if let ElgatoLight::Status { output, .. } = args {
+        if !statuses.is_empty() {
+            print!("{}", output::render(*output, &statuses.concat())?);
+        }
+    }
+
+    result
+}
+
+async fn serve(
+    config_path: Option<PathBuf>,
+    socket: Option<PathBuf>,
+    options: RequestOptions,
+    bind: SocketAddr,
+    token: Option<String>,
+) -> Result<(), Error> {
+    let listener = TcpListener::bind(bind)
+        .map_err(|e| Error::InvalidAddress(format!("Unable to listen on {}: {}", bind, e)))?;
+    let address = listener
+        .local_addr()
+        .map_err(|e| Error::Other(format!("Unable to listen on {}: {}", bind, e)))?;
+
+    if token.is_none() && !address.ip().is_loopback() {
+        eprintln!(
+            "Warning: anyone who can reach {} can control the lights. Use --token to require a token",
+            address
+        );
+    }
+    println!("Listening on http://{}", address);
+    Server::new(config_path, socket, options, token)
+        .serve(listener)
+        .await
+}
+
+async fn run_mqtt(
+    bridge: BridgeOptions,
+    config: &Config,
+    options: RequestOptions,
+) -> Result<(), Error> {
+    if config.lights.is_empty() {
+        return Err(Error::Config(
+            "There are no named lights to bridge. Add one with \"elgato-light light add\""
+                .to_string(),
+        ));
+    }
+    if bridge.interval.is_zero() {
+        return Err(Error::InvalidInput(
+            "The interval must be longer than zero".to_string(),
+        ));
+    }
+
+    let lights = config
+        .lights
+        .iter()
+        .map(|(name, light)| (name.clone(), light.ip_address.clone()))
+        .collect();
+    println!("Bridging to {}", bridge.broker);
+    mqtt::run(bridge, lights, options).await
+}
+
+#[cfg(unix)]
+async fn run_daemon(cli: &Cli, config: &Config, options: RequestOptions) -> Result<(), Error> {
+    let path = cli.socket_path()?;
+    let listener = daemon::listen(&path).await?;
+    let daemon = Arc::new(Daemon::new(options));
+
+    // Connect to the named lights up front, so the first command for each is fast too.
+    for (name, light) in &config.lights {
+        let daemon = daemon.clone();
+        let name = name.clone();
+        let address = light.ip_address.clone();
+        tokio::spawn(async move {
+            if let Err(e) = daemon.connect(&address, options).await {
+                eprintln!("{}: {}", name, e);
+            }
+        });
+    }
+
+    println!("Listening on {}", path.display());
+    daemon.run(listener, &path).await
+}
 
-    Ok(())
+#[cfg(not(unix))]
+async fn run_daemon(_cli: &Cli, _config: &Config, _options: RequestOptions) -> Result<(), Error> {
+    Err(Error::Other(
+        "The daemon needs Unix domain sockets, which this platform doesn't have".to_string(),
+    ))
 }
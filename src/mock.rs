@@ -0,0 +1,409 @@
+//! A stand-in for the HTTP API of an Elgato light, for trying the CLI and testing without one.
+//!
+//! It answers the same requests a Key Light does and rejects values a light would, such as a
+//! brightness over 100, with HTTP 400 and no change to its state.
+
+use crate::keylight::{AccessoryInfo, Light, Settings, Status};
+use crate::temperature::{MAX_DEVICE, MIN_DEVICE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::oneshot;
+
+/// The state of the mock device, shared with the server.
+#[derive(Debug)]
+struct Device {
+    lights: Vec<Light>,
+    accessory_info: AccessoryInfo,
+    settings: Settings,
+    identify: bool,
+    identified: usize,
+    requests: usize,
+}
+
+impl Device {
+    fn new(number_of_lights: usize) -> Device {
+        let mut settings = Settings {
+            power_on_behavior: 1,
+            power_on_brightness: 20,
+            power_on_temperature: 213,
+            switch_on_duration_ms: 100,
+            switch_off_duration_ms: 300,
+            ..Default::default()
+        };
+        settings
+            .other
+            .insert("colorChangeDurationMs".to_string(), 100.into());
+
+        Device {
+            lights: vec![
+                Light {
+                    on: 0,
+                    brightness: 20,
+                    temperature: 213,
+                };
+                number_of_lights
+            ],
+            accessory_info: AccessoryInfo {
+                product_name: "Elgato Key Light".to_string(),
+                hardware_board_type: 53,
+                firmware_build_number: 218,
+                firmware_version: "1.0.3".to_string(),
+                serial_number: "BW33J1A02740".to_string(),
+                display_name: String::new(),
+                features: vec!["lights".to_string()],
+            },
+            settings,
+            identify: true,
+            identified: 0,
+            requests: 0,
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            number_of_lights: self.lights.len(),
+            lights: self.lights.clone(),
+        }
+    }
+}
+
+/// A port on 127.0.0.1 that nothing is listening on, for testing lights that can't be reached.
+/// It's found by binding a listener and dropping it.
+pub fn unused_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("a free port")
+        .port()
+}
+
+/// A mock Elgato light listening on a local port. It stops when dropped.
+///
+/// ```no_run
+/// use elgato_light::mock::MockLight;
+///
+/// # async fn example() -> std::io::Result<()> {
+/// let light = MockLight::start(2).await?;
+/// println!("Listening on {}", light.address());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MockLight {
+    address: SocketAddr,
+    device: Arc<Mutex<Device>>,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl MockLight {
+    /// Starts a device with `number_of_lights` lights on a free port on 127.0.0.1.
+    pub async fn start(number_of_lights: usize) -> io::Result<MockLight> {
+        MockLight::bind(SocketAddr::from(([127, 0, 0, 1], 0)), number_of_lights).await
+    }
+
+    /// Starts a device with `number_of_lights` lights on `address`.
+    pub async fn bind(address: SocketAddr, number_of_lights: usize) -> io::Result<MockLight> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let device = Arc::new(Mutex::new(Device::new(number_of_lights)));
+
+        let shared = device.clone();
+        let make_service = make_service_fn(move |_| {
+            let device = shared.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| handle(device.clone(), request)))
+            }
+        });
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)
+            .map_err(io::Error::other)?
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                stopped.await.ok();
+            });
+        tokio::spawn(server);
+
+        Ok(MockLight {
+            address,
+            device,
+            _shutdown: shutdown,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn lights(&self) -> Vec<Light> {
+        self.device().lights.clone()
+    }
+
+    /// Puts the light at `index` in a state, as if it had been changed with the buttons on it.
+    pub fn set_light(&self, index: usize, light: Light) {
+        self.device().lights[index] = light;
+    }
+
+    pub fn accessory_info(&self) -> AccessoryInfo {
+        self.device().accessory_info.clone()
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.device().settings.clone()
+    }
+
+    /// Whether the device has an identify endpoint, like newer firmware does. It has by default.
+    pub fn set_identify(&self, supported: bool) {
+        self.device().identify = supported;
+    }
+
+    /// How many times the device has been asked to identify itself.
+    pub fn identified(&self) -> usize {
+        self.device().identified
+    }
+
+    /// How many requests the device has answered.
+    pub fn requests(&self) -> usize {
+        self.device().requests
+    }
+
+    fn device(&self) -> MutexGuard<'_, Device> {
+        self.device.lock().unwrap()
+    }
+}
+
+/// The fields a request can change on one light. Fields left out keep their value.
+#[derive(Debug, Default, Deserialize)]
+struct LightChange {
+    on: Option<u8>,
+    brightness: Option<u8>,
+    temperature: Option<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LightsChange {
+    lights: Vec<LightChange>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessoryInfoChange {
+    display_name: Option<String>,
+}
+
+async fn handle(
+    device: Arc<Mutex<Device>>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return Ok(bad_request(e.to_string())),
+    };
+    let mut device = device.lock().unwrap();
+    device.requests += 1;
+
+    let response = match (method, path.as_str()) {
+        (Method::GET, "/elgato/lights") => json(&device.status()),
+        (Method::PUT, "/elgato/lights") => match change_lights(&mut device, &body) {
+            Ok(()) => json(&device.status()),
+            Err(reason) => bad_request(reason),
+        },
+        (Method::GET, "/elgato/accessory-info") => json(&device.accessory_info),
+        (Method::PUT, "/elgato/accessory-info") => {
+            match serde_json::from_slice::<AccessoryInfoChange>(&body) {
+                Ok(change) => {
+                    if let Some(name) = change.display_name {
+                        device.accessory_info.display_name = name;
+                    }
+                    json(&device.accessory_info)
+                }
+                Err(e) => bad_request(e.to_string()),
+            }
+        }
+        (Method::GET, "/elgato/lights/settings") => json(&device.settings),
+        (Method::PUT, "/elgato/lights/settings") => match change_settings(&mut device, &body) {
+            Ok(()) => json(&device.settings),
+            Err(reason) => bad_request(reason),
+        },
+        (Method::POST, "/elgato/identify") if device.identify => {
+            device.identified += 1;
+            empty(StatusCode::OK)
+        }
+        (_, "/elgato/lights" | "/elgato/accessory-info" | "/elgato/lights/settings") => {
+            empty(StatusCode::METHOD_NOT_ALLOWED)
+        }
+        _ => empty(StatusCode::NOT_FOUND),
+    };
+
+    Ok(response)
+}
+
+/// Applies a change to the lights, all or nothing. Lights are changed in order, so a request can
+/// leave out the ones after the last it changes.
+fn change_lights(device: &mut Device, body: &[u8]) -> Result<(), String> {
+    let change: LightsChange = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    if change.lights.len() > device.lights.len() {
+        return Err(format!(
+            "{} lights sent, the device has {}",
+            change.lights.len(),
+            device.lights.len()
+        ));
+    }
+
+    let mut lights = device.lights.clone();
+    for (light, change) in lights.iter_mut().zip(change.lights) {
+        if let Some(on) = change.on {
+            light.on = check("on", on, 0, 1)?;
+        }
+        if let Some(brightness) = change.brightness {
+            light.brightness = check("brightness", brightness, 0, 100)?;
+        }
+        if let Some(temperature) = change.temperature {
+            light.temperature = check("temperature", temperature, MIN_DEVICE, MAX_DEVICE)?;
+        }
+    }
+
+    device.lights = lights;
+    Ok(())
+}
+
+/// Merges the sent fields into the settings, all or nothing.
+fn change_settings(device: &mut Device, body: &[u8]) -> Result<(), String> {
+    let change: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    let mut settings = match serde_json::to_value(&device.settings) {
+        Ok(serde_json::Value::Object(settings)) => settings,
+        _ => unreachable!("settings serialize to an object"),
+    };
+    settings.extend(change);
+
+    let settings: Settings = serde_json::from_value(settings.into()).map_err(|e| e.to_string())?;
+    check("powerOnBehavior", settings.power_on_behavior, 1, 2)?;
+    check("powerOnBrightness", settings.power_on_brightness, 0, 100)?;
+    check(
+        "powerOnTemperature",
+        settings.power_on_temperature,
+        MIN_DEVICE,
+        MAX_DEVICE,
+    )?;
+
+    device.settings = settings;
+    Ok(())
+}
+
+fn check<T: PartialOrd + std::fmt::Display>(
+    field: &str,
+    value: T,
+    min: T,
+    max: T,
+) -> Result<T, String> {
+    if value < min || value > max {
+        return Err(format!(
+            "{} must be between {} and {}, got {}",
+            field, min, max, value
+        ));
+    }
+    Ok(value)
+}
+
+fn json<T: Serialize>(body: &T) -> Response<Body> {
+    let body = serde_json::to_vec(body).expect("the device state serializes");
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .expect("the response is valid")
+}
+
+fn bad_request(reason: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::from(reason))
+        .expect("the response is valid")
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("the response is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::keylight::{KeyLight, LightUpdate, RequestOptions};
+
+    async fn connect(light: &MockLight) -> KeyLight {
+        KeyLight::new_from_ip(light.address(), RequestOptions::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn changes_only_the_lights_and_fields_sent() {
+        let light = MockLight::start(2).await.unwrap();
+        let keylight = connect(&light).await;
+
+        keylight
+            .set_lights(&[LightUpdate {
+                on: Some(1),
+                brightness: Some(60),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let lights = light.lights();
+        assert_eq!((lights[0].on, lights[0].brightness), (1, 60));
+        assert_eq!(lights[0].temperature, 213);
+        assert_eq!(lights[1].on, 0);
+    }
+
+    #[tokio::test]
+    async fn rejects_values_out_of_range_without_changing_anything() {
+        let light = MockLight::start(2).await.unwrap();
+        let keylight = connect(&light).await;
+        let before = light.lights();
+
+        let result = keylight
+            .set_lights(&[
+                LightUpdate {
+                    brightness: Some(50),
+                    ..Default::default()
+                },
+                LightUpdate {
+                    temperature: Some(MAX_DEVICE + 1),
+                    ..Default::default()
+                },
+            ])
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(Error::Http {
+                status: StatusCode::BAD_REQUEST,
+                ..
+            })
+        ));
+        assert_eq!(light.lights(), before);
+    }
+
+    #[tokio::test]
+    async fn keeps_settings_it_does_not_know_about() {
+        let light = MockLight::start(1).await.unwrap();
+        let keylight = connect(&light).await;
+
+        let mut settings = keylight.settings().await.unwrap();
+        settings.power_on_brightness = 40;
+        keylight.set_settings(&settings).await.unwrap();
+
+        assert_eq!(light.settings(), settings);
+        assert!(light.settings().other.contains_key("colorChangeDurationMs"));
+    }
+}
@@ -0,0 +1,622 @@
+//! Bridges lights to an MQTT broker, with Home Assistant discovery so they show up as lights with
+//! brightness and color temperature.
+//!
+//! Each light publishes its state to `{prefix}/{light}/state` and takes commands on
+//! `{prefix}/{light}/set`, both as JSON in the format of Home Assistant's JSON schema:
+//!
+//! ```json
+//! {"state": "ON", "brightness": 40, "color_temp": 4000, "transition": 0.5}
+//! ```
+//!
+//! `brightness` is in percent and `color_temp` in Kelvin. `{light}` is the light's name in the
+//! config file, followed by `-0`, `-1` and so on for devices with several lights.
+
+use crate::address::Address;
+use crate::controller::{LightController, LightState, LightTarget, Operation};
+use crate::error::Error;
+use crate::fade::{Easing, Fade};
+use crate::keylight::{AccessoryInfo, RequestOptions};
+use crate::temperature::{Temperature, MAX_KELVIN, MIN_KELVIN};
+use futures::future::join_all;
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{self, MissedTickBehavior};
+
+pub const DEFAULT_PORT: u16 = 1883;
+pub const DEFAULT_PREFIX: &str = "elgato-light";
+pub const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// How long to wait before trying the broker again after losing the connection.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The host and port of an MQTT broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Broker {
+    /// A hostname or IP address, with IPv6 addresses in brackets.
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for Broker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+/// Parses a broker such as `mqtt://192.168.1.10:1883`, `broker.local` or `[::1]:1884`. The port
+/// defaults to 1883.
+pub fn parse_broker(s: &str) -> Result<Broker, String> {
+    let url = match s.split_once("://") {
+        Some(("mqtt" | "tcp", _)) => s.to_string(),
+        Some((scheme, _)) => {
+            return Err(format!(
+                "Unsupported scheme \"{}\". Use mqtt:// or leave it out",
+                scheme
+            ))
+        }
+        None => format!("mqtt://{}", s),
+    };
+    let invalid = || format!("Invalid broker \"{}\"", s);
+    let url = reqwest::Url::parse(&url).map_err(|_| invalid())?;
+    if url.path().len() > 1 || url.query().is_some() || !url.username().is_empty() {
+        return Err(invalid());
+    }
+    let host = url
+        .host_str()
+        .filter(|host| !host.is_empty())
+        .ok_or_else(invalid)?;
+
+    Ok(Broker {
+        host: host.to_string(),
+        port: url.port().unwrap_or(DEFAULT_PORT),
+    })
+}
+
+/// Where the broker is, and the topics to use on it.
+#[derive(Debug, Clone)]
+pub struct BridgeOptions {
+    pub broker: Broker,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The start of every topic the bridge publishes to or listens on.
+    pub prefix: String,
+    /// Where Home Assistant listens for discovery messages, or `None` to send none.
+    pub discovery_prefix: Option<String>,
+    /// How often to read the lights, to publish changes made by other apps or on the lights.
+    pub interval: Duration,
+}
+
+/// A command from `{prefix}/{light}/set`.
+#[derive(Debug, Default, Deserialize)]
+struct Command {
+    state: Option<String>,
+    brightness: Option<u8>,
+    color_temp: Option<u32>,
+    /// Seconds to fade over.
+    transition: Option<f64>,
+}
+
+/// What the bridge hears from the broker.
+#[derive(Debug)]
+enum Message {
+    Connected,
+    Publish { topic: String, payload: Vec<u8> },
+}
+
+/// One device from the config file, which may have several lights.
+#[derive(Debug)]
+struct Device {
+    /// The device's name in the config file, made safe to use in topics.
+    id: String,
+    address: Address,
+    /// The connection to the device, or `None` while it can't be reached.
+    light: Option<LightController>,
+    info: AccessoryInfo,
+    number_of_lights: usize,
+    /// The last state published for each light.
+    published: Vec<Option<LightState>>,
+    /// Whether the last attempt to read the device failed.
+    failing: bool,
+}
+
+impl Device {
+    fn new(name: &str, address: Address) -> Device {
+        Device {
+            id: topic_id(name),
+            address,
+            light: None,
+            info: AccessoryInfo::default(),
+            number_of_lights: 0,
+            published: Vec::new(),
+            failing: false,
+        }
+    }
+
+    /// The topic id of the light at `index`.
+    fn light_id(&self, index: usize) -> String {
+        if self.number_of_lights == 1 {
+            self.id.clone()
+        } else {
+            format!("{}-{}", self.id, index)
+        }
+    }
+}
+
+/// Publishes the state of the lights and runs the commands sent to them.
+struct Bridge {
+    client: AsyncClient,
+    options: BridgeOptions,
+    request_options: RequestOptions,
+    devices: Vec<Device>,
+}
+
+/// Connects to the broker and bridges the named `lights` until the process is interrupted or
+/// terminated.
+///
+/// Lights that can't be reached are marked unavailable and tried again every
+/// [`BridgeOptions::interval`], and the broker is reconnected to whenever the connection drops.
+pub async fn run(
+    options: BridgeOptions,
+    lights: Vec<(String, Address)>,
+    request_options: RequestOptions,
+) -> Result<(), Error> {
+    let status_topic = format!("{}/status", options.prefix);
+    let mut mqtt_options = MqttOptions::new(
+        format!("{}-{}", DEFAULT_PREFIX, std::process::id()),
+        &options.broker.host,
+        options.broker.port,
+    );
+    mqtt_options
+        .set_keep_alive(Duration::from_secs(30))
+        .set_last_will(LastWill::new(
+            &status_topic,
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+    if let Some(username) = &options.username {
+        mqtt_options.set_credentials(username, options.password.as_deref().unwrap_or_default());
+    }
+
+    let (client, mut event_loop) = AsyncClient::new(mqtt_options, 64);
+    let (messages, mut received) = mpsc::channel(64);
+    let broker = options.broker.clone();
+    let events = tokio::spawn(async move {
+        loop {
+            let message = match event_loop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => Message::Connected,
+                Ok(Event::Incoming(Packet::Publish(publish))) => Message::Publish {
+                    topic: publish.topic,
+                    payload: publish.payload.to_vec(),
+                },
+                Ok(Event::Outgoing(rumqttc::Outgoing::Disconnect)) => return,
+                Ok(_) => continue,
+                Err(e) => {
+                    eprintln!("Unable to reach the MQTT broker at {}: {}", broker, e);
+                    time::sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
+            if messages.send(message).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut bridge = Bridge {
+        client,
+        devices: lights
+            .iter()
+            .map(|(name, address)| Device::new(name, address.clone()))
+            .collect(),
+        options,
+        request_options,
+    };
+    let mut interval = time::interval(bridge.options.interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let bridging = async {
+        loop {
+            tokio::select! {
+                Some(message) = received.recv() => match message {
+                    Message::Connected => bridge.announce().await,
+                    Message::Publish { topic, payload } => bridge.command(&topic, &payload).await,
+                },
+                _ = interval.tick() => bridge.refresh().await,
+            }
+        }
+    };
+    // Stop even in the middle of reading a light that doesn't answer.
+    tokio::select! {
+        _ = bridging => {}
+        _ = shutdown_signal() => {}
+    }
+
+    // Say goodbye, since the broker only sends the last will when the connection drops.
+    let _ = bridge
+        .client
+        .publish(&status_topic, QoS::AtLeastOnce, true, "offline")
+        .await;
+    let _ = bridge.client.disconnect().await;
+    let _ = time::timeout(Duration::from_secs(1), events).await;
+    Ok(())
+}
+
+impl Bridge {
+    fn topic(&self, id: &str, name: &str) -> String {
+        format!("{}/{}/{}", self.options.prefix, id, name)
+    }
+
+    /// Announces the bridge and its lights, after connecting or reconnecting to the broker.
+    async fn announce(&mut self) {
+        let status_topic = format!("{}/status", self.options.prefix);
+        self.publish(&status_topic, "online".to_string()).await;
+        let commands = format!("{}/+/set", self.options.prefix);
+        if let Err(e) = self.client.subscribe(&commands, QoS::AtLeastOnce).await {
+            eprintln!("Unable to subscribe to {}: {}", commands, e);
+        }
+
+        for index in 0..self.devices.len() {
+            self.devices[index].published.fill(None);
+            if self.devices[index].light.is_some() {
+                self.announce_device(index).await;
+            }
+        }
+        self.refresh().await;
+    }
+
+    /// Publishes the discovery messages for a device's lights, and marks it available.
+    async fn announce_device(&self, index: usize) {
+        let device = &self.devices[index];
+        if let Some(discovery_prefix) = &self.options.discovery_prefix {
+            for light in 0..device.number_of_lights {
+                let config = self.discovery(device, light);
+                let topic = format!(
+                    "{}/light/{}/config",
+                    discovery_prefix,
+                    config["unique_id"].as_str().unwrap_or_default()
+                );
+                self.publish(&topic, config.to_string()).await;
+            }
+        }
+        self.publish(
+            &self.topic(&device.id, "availability"),
+            "online".to_string(),
+        )
+        .await;
+    }
+
+    /// The Home Assistant discovery message for the light at `index` on the device.
+    fn discovery(&self, device: &Device, index: usize) -> Value {
+        let device_id = if device.info.serial_number.is_empty() {
+            device.id.clone()
+        } else {
+            device.info.serial_number.clone()
+        };
+        let light_id = device.light_id(index);
+        // Lights on a device with one light are named after the device.
+        let name = match device.number_of_lights {
+            1 => Value::Null,
+            _ => Value::String(format!("Light {}", index + 1)),
+        };
+
+        json!({
+            "name": name,
+            "unique_id": format!("elgato_{}_{}", topic_id(&device_id), index),
+            "schema": "json",
+            "state_topic": self.topic(&light_id, "state"),
+            "command_topic": self.topic(&light_id, "set"),
+            "availability": [
+                { "topic": format!("{}/status", self.options.prefix) },
+                { "topic": self.topic(&device.id, "availability") },
+            ],
+            "availability_mode": "all",
+            "brightness": true,
+            "brightness_scale": 100,
+            "supported_color_modes": ["color_temp"],
+            "color_temp_kelvin": true,
+            "min_kelvin": MIN_KELVIN,
+            "max_kelvin": MAX_KELVIN,
+            "device": {
+                "identifiers": [format!("elgato_{}", topic_id(&device_id))],
+                "name": device.info.name(),
+                "manufacturer": "Elgato",
+                "model": device.info.product_name,
+                "sw_version": device.info.firmware_version,
+                "serial_number": device.info.serial_number,
+            },
+        })
+    }
+
+    /// Connects to the devices that aren't connected yet, and publishes the state of every light
+    /// that has changed. Devices are read at the same time, so one that doesn't answer doesn't
+    /// hold up the rest.
+    async fn refresh(&mut self) {
+        let readings = join_all(
+            self.devices
+                .iter()
+                .map(|device| read(device, self.request_options)),
+        )
+        .await;
+        for (index, reading) in readings.into_iter().enumerate() {
+            self.update(index, reading).await;
+        }
+    }
+
+    /// Publishes what reading a device found: its discovery messages when it has just connected,
+    /// and the state of each light that has changed since it was last published. A device that
+    /// can't be reached is marked unavailable until it reconnects.
+    async fn update(&mut self, index: usize, reading: Result<Reading, Error>) {
+        let (connected, states) = match reading {
+            Ok(reading) => reading,
+            Err(e) => {
+                let device = &mut self.devices[index];
+                // Say why once, rather than every time the device is tried again.
+                if !device.failing {
+                    eprintln!("{}: {}", device.id, e);
+                    device.failing = true;
+                }
+                if e.is_retryable() && device.light.take().is_some() {
+                    let topic = self.topic(&self.devices[index].id, "availability");
+                    self.publish(&topic, "offline".to_string()).await;
+                }
+                return;
+            }
+        };
+
+        let device = &mut self.devices[index];
+        device.failing = false;
+        if let Some((light, info)) = connected {
+            device.number_of_lights = light.keylight().number_of_lights();
+            device.published = vec![None; device.number_of_lights];
+            device.info = info;
+            device.light = Some(light);
+            self.announce_device(index).await;
+        }
+
+        for state in states {
+            let device = &self.devices[index];
+            if device.published.get(state.index) == Some(&Some(state.clone())) {
+                continue;
+            }
+            let topic = self.topic(&device.light_id(state.index), "state");
+            self.publish(&topic, state_payload(&state).to_string())
+                .await;
+            if let Some(published) = self.devices[index].published.get_mut(state.index) {
+                *published = Some(state);
+            }
+        }
+    }
+
+    /// Runs a command sent to `{prefix}/{light}/set`, then publishes the new state.
+    async fn command(&mut self, topic: &str, payload: &[u8]) {
+        let Some(id) = topic
+            .strip_prefix(&format!("{}/", self.options.prefix))
+            .and_then(|rest| rest.strip_suffix("/set"))
+        else {
+            return;
+        };
+        let Some((index, light)) = self.devices.iter().enumerate().find_map(|(index, device)| {
+            (0..device.number_of_lights)
+                .find(|light| device.light_id(*light) == id)
+                .map(|light| (index, light))
+        }) else {
+            eprintln!("{}: Unknown light \"{}\"", topic, id);
+            return;
+        };
+
+        let result = async {
+            let (operation, fade) = parse_command(payload)?;
+            let mut controller = self.devices[index]
+                .light
+                .clone()
+                .ok_or_else(|| Error::Other("The light is unavailable".to_string()))?;
+            controller.select(Some(light))?;
+            controller.set_fade(fade);
+            controller.perform(operation).await
+        }
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("{}: {}", id, e);
+        }
+        let reading = read(&self.devices[index], self.request_options).await;
+        self.update(index, reading).await;
+    }
+
+    /// Publishes a retained message, so whoever subscribes later gets the latest one straight
+    /// away.
+    async fn publish(&self, topic: &str, payload: String) {
+        if let Err(e) = self
+            .client
+            .publish(topic, QoS::AtLeastOnce, true, payload)
+            .await
+        {
+            eprintln!("Unable to publish to {}: {}", topic, e);
+        }
+    }
+}
+
+/// A device's connection and accessory info when it has just connected, and the state of its
+/// lights.
+type Reading = (Option<(LightController, AccessoryInfo)>, Vec<LightState>);
+
+/// Reads the state of the device's lights, connecting to it first if it isn't connected.
+async fn read(device: &Device, options: RequestOptions) -> Result<Reading, Error> {
+    if let Some(light) = &device.light {
+        return Ok((None, light.state().await?));
+    }
+
+    let light =
+        LightController::connect(&LightTarget::new(device.address.clone()), options).await?;
+    let info = light.keylight().accessory_info().await?;
+    let states = light.state().await?;
+    Ok((Some((light, info)), states))
+}
+
+fn state_payload(state: &LightState) -> Value {
+    json!({
+        "state": if state.on { "ON" } else { "OFF" },
+        "brightness": state.brightness,
+        "color_temp": state.temperature.kelvin(),
+        "color_mode": "color_temp",
+    })
+}
+
+/// Parses a command into the change to make and how to fade to it.
+fn parse_command(payload: &[u8]) -> Result<(Operation, Option<Fade>), Error> {
+    let command: Command = serde_json::from_slice(payload)
+        .map_err(|e| Error::InvalidInput(format!("Invalid command: {}", e)))?;
+
+    let on = match command.state.as_deref() {
+        Some("ON") => Some(true),
+        Some("OFF") => Some(false),
+        Some(state) => {
+            return Err(Error::InvalidInput(format!(
+                "Unknown state \"{}\". Use ON or OFF",
+                state
+            )))
+        }
+        None => None,
+    };
+    let brightness = command.brightness.map(|brightness| brightness.min(100));
+    let temperature = command
+        .color_temp
+        .map(|kelvin| Temperature::from_kelvin(kelvin.clamp(MIN_KELVIN, MAX_KELVIN)))
+        .transpose()
+        .map_err(Error::InvalidInput)?;
+    if on.is_none() && brightness.is_none() && temperature.is_none() {
+        return Err(Error::InvalidInput(
+            "Nothing to change. Send state, brightness or color_temp".to_string(),
+        ));
+    }
+
+    let fade = command
+        .transition
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .filter(|duration| !duration.is_zero())
+        .map(|duration| Fade {
+            duration,
+            easing: Easing::EaseInOut,
+        });
+
+    Ok((
+        Operation::Change {
+            on,
+            brightness,
+            temperature,
+        },
+        fade,
+    ))
+}
+
+/// `name` with anything that isn't a letter, digit, `-` or `_` replaced, since MQTT topics and
+/// Home Assistant ids can't have some characters.
+fn topic_id(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_home_assistant_commands() {
+        let (operation, fade) =
+            parse_command(br#"{"state": "ON", "brightness": 40, "color_temp": 4000}"#).unwrap();
+        assert_eq!(
+            operation,
+            Operation::Change {
+                on: Some(true),
+                brightness: Some(40),
+                temperature: Temperature::from_kelvin(4000).ok(),
+            }
+        );
+        assert_eq!(fade, None);
+
+        let (operation, fade) = parse_command(br#"{"state": "OFF", "transition": 1.5}"#).unwrap();
+        assert_eq!(
+            operation,
+            Operation::Change {
+                on: Some(false),
+                brightness: None,
+                temperature: None,
+            }
+        );
+        assert_eq!(fade.unwrap().duration, Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn clamps_commands_to_what_the_light_supports() {
+        let (operation, _) = parse_command(br#"{"brightness": 255, "color_temp": 2000}"#).unwrap();
+        assert_eq!(
+            operation,
+            Operation::Change {
+                on: None,
+                brightness: Some(100),
+                temperature: Temperature::from_kelvin(MIN_KELVIN).ok(),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_commands() {
+        assert!(parse_command(b"ON").is_err());
+        assert!(parse_command(b"{}").is_err());
+        assert!(parse_command(br#"{"state": "DIM"}"#).is_err());
+        assert!(parse_command(br#"{"state": "ON", "transition": -1}"#).is_ok());
+    }
+
+    #[test]
+    fn parses_brokers() {
+        let broker = |host: &str, port| Broker {
+            host: host.to_string(),
+            port,
+        };
+        assert_eq!(
+            parse_broker("broker.local"),
+            Ok(broker("broker.local", 1883))
+        );
+        assert_eq!(
+            parse_broker("mqtt://192.168.1.10:1884"),
+            Ok(broker("192.168.1.10", 1884))
+        );
+        assert_eq!(parse_broker("[::1]:1884"), Ok(broker("[::1]", 1884)));
+        assert!(parse_broker("mqtts://broker.local").is_err());
+        assert!(parse_broker("broker.local/topic").is_err());
+        assert!(parse_broker("").is_err());
+    }
+
+    #[test]
+    fn makes_names_safe_for_topics() {
+        assert_eq!(topic_id("desk-key_2"), "desk-key_2");
+        assert_eq!(topic_id("desk key/#1"), "desk_key__1");
+    }
+}
@@ -0,0 +1,264 @@
+use crate::controller::Outcome;
+use crate::error::Error;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Json,
+    Yaml,
+    Table,
+    Plain,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "table" => Ok(OutputFormat::Table),
+            "plain" => Ok(OutputFormat::Plain),
+            _ => Err(format!(
+                "Unknown output format \"{}\". Use json, yaml, table or plain",
+                s
+            )),
+        }
+    }
+}
+
+/// Something the CLI prints, in any of the output formats.
+pub trait Record: Serialize {
+    /// Column headings for the table format.
+    const HEADER: &'static [&'static str];
+
+    /// The cells of the record's table row, in the same order as [`Record::HEADER`].
+    fn row(&self) -> Vec<String>;
+
+    /// The record in the plain format, ending with a newline.
+    fn plain(&self) -> String;
+}
+
+/// The state of one light on a device, as printed by the status command.
+///
+/// JSON and YAML output is a list of these, one per light on each targeted device:
+///
+/// ```json
+/// [{"light": "desk", "name": "Desk Key Light", "index": 0, "on": true, "brightness": 20, "temperature": 4000}]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LightStatus {
+    /// The name or IP address the light was targeted with.
+    pub light: String,
+    /// The name set on the device, or its product name when it has none.
+    pub name: String,
+    /// Position of the light on the device, starting at 0.
+    pub index: usize,
+    pub on: bool,
+    /// Brightness in percent (0-100).
+    pub brightness: u8,
+    /// Color temperature in Kelvin.
+    pub temperature: u32,
+}
+
+impl LightStatus {
+    /// The status of each light read by [`Operation::State`], labelled with what the light was
+    /// targeted with. Other outcomes don't have any.
+    ///
+    /// [`Operation::State`]: crate::controller::Operation::State
+    pub fn from_outcome(label: &str, outcome: Outcome) -> Vec<LightStatus> {
+        let Outcome::State { name, lights } = outcome else {
+            return Vec::new();
+        };
+
+        lights
+            .into_iter()
+            .map(|state| LightStatus {
+                light: label.to_string(),
+                name: name.clone(),
+                index: state.index,
+                on: state.on,
+                brightness: state.brightness,
+                temperature: state.temperature.kelvin(),
+            })
+            .collect()
+    }
+}
+
+impl Record for LightStatus {
+    const HEADER: &'static [&'static str] =
+        &["LIGHT", "NAME", "INDEX", "ON", "BRIGHTNESS", "TEMPERATURE"];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.light.clone(),
+            self.name.clone(),
+            self.index.to_string(),
+            if self.on { "yes" } else { "no" }.to_string(),
+            format!("{}%", self.brightness),
+            format!("{}K", self.temperature),
+        ]
+    }
+
+    fn plain(&self) -> String {
+        format!(
+            "{} ({}) [{}]: {}, {}%, {}K\n",
+            self.light,
+            self.name,
+            self.index,
+            if self.on { "on" } else { "off" },
+            self.brightness,
+            self.temperature
+        )
+    }
+}
+
+/// A device's accessory info, as printed by the info command.
+///
+/// JSON and YAML output is a list of these, one per targeted device:
+///
+/// ```json
+/// [{"light": "desk", "product_name": "Elgato Key Light", "display_name": "Desk", "serial_number": "BW33J1A02345", "firmware_version": "1.0.3", "firmware_build_number": 192, "hardware_board_type": 53, "features": ["lights"]}]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceInfo {
+    /// The name or IP address the light was targeted with.
+    pub light: String,
+    pub product_name: String,
+    /// The name set on the device, which may be empty.
+    pub display_name: String,
+    pub serial_number: String,
+    pub firmware_version: String,
+    pub firmware_build_number: u32,
+    pub hardware_board_type: u32,
+    pub features: Vec<String>,
+}
+
+impl Record for DeviceInfo {
+    const HEADER: &'static [&'static str] = &[
+        "LIGHT", "PRODUCT", "NAME", "SERIAL", "FIRMWARE", "BOARD", "FEATURES",
+    ];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.light.clone(),
+            self.product_name.clone(),
+            self.display_name.clone(),
+            self.serial_number.clone(),
+            format!("{} ({})", self.firmware_version, self.firmware_build_number),
+            self.hardware_board_type.to_string(),
+            self.features.join(","),
+        ]
+    }
+
+    fn plain(&self) -> String {
+        format!(
+            "{}: {}, serial {}, firmware {} (build {}), board type {}, name \"{}\", features {}\n",
+            self.light,
+            self.product_name,
+            self.serial_number,
+            self.firmware_version,
+            self.firmware_build_number,
+            self.hardware_board_type,
+            self.display_name,
+            self.features.join(", ")
+        )
+    }
+}
+
+/// A device's settings, as printed by the settings get command.
+///
+/// JSON and YAML output is a list of these, one per targeted device:
+///
+/// ```json
+/// [{"light": "desk", "power_on_behavior": "restore", "power_on_brightness": 20, "power_on_temperature": 4695, "switch_on_duration_ms": 100, "switch_off_duration_ms": 300}]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LightSettings {
+    /// The name or IP address the light was targeted with.
+    pub light: String,
+    /// `restore` to come back in the last state after losing power, `default` to come back on
+    /// with the power-on brightness and temperature, or the device's value when it's neither.
+    pub power_on_behavior: String,
+    /// Brightness in percent (0-100).
+    pub power_on_brightness: u8,
+    /// Color temperature in Kelvin.
+    pub power_on_temperature: u32,
+    pub switch_on_duration_ms: u32,
+    pub switch_off_duration_ms: u32,
+}
+
+impl Record for LightSettings {
+    const HEADER: &'static [&'static str] = &[
+        "LIGHT",
+        "POWER ON",
+        "BRIGHTNESS",
+        "TEMPERATURE",
+        "SWITCH ON",
+        "SWITCH OFF",
+    ];
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.light.clone(),
+            self.power_on_behavior.clone(),
+            format!("{}%", self.power_on_brightness),
+            format!("{}K", self.power_on_temperature),
+            format!("{}ms", self.switch_on_duration_ms),
+            format!("{}ms", self.switch_off_duration_ms),
+        ]
+    }
+
+    fn plain(&self) -> String {
+        format!(
+            "{}: power on {}, {}%, {}K, switch on {}ms, switch off {}ms\n",
+            self.light,
+            self.power_on_behavior,
+            self.power_on_brightness,
+            self.power_on_temperature,
+            self.switch_on_duration_ms,
+            self.switch_off_duration_ms
+        )
+    }
+}
+
+pub fn render<T: Record>(format: OutputFormat, records: &[T]) -> Result<String, Error> {
+    let output = match format {
+        OutputFormat::Json => serde_json::to_string_pretty(records).map_err(output_error)? + "\n",
+        OutputFormat::Yaml => serde_yaml::to_string(records).map_err(output_error)?,
+        OutputFormat::Table => render_table(records),
+        OutputFormat::Plain => records.iter().map(Record::plain).collect(),
+    };
+
+    Ok(output)
+}
+
+fn output_error(e: impl fmt::Display) -> Error {
+    Error::Other(format!("Unable to format the output: {}", e))
+}
+
+fn render_table<T: Record>(records: &[T]) -> String {
+    let header: Vec<String> = T::HEADER.iter().map(|cell| cell.to_string()).collect();
+    let rows: Vec<Vec<String>> = records.iter().map(Record::row).collect();
+
+    let mut widths: Vec<usize> = header.iter().map(String::len).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            cells.join("  ").trim_end().to_string() + "\n"
+        })
+        .collect()
+}
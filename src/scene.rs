@@ -0,0 +1,78 @@
+use crate::address::Address;
+use crate::config::{self, Config};
+use crate::connector::Connector;
+use crate::controller::{LightState, LightTarget, Operation};
+use crate::error::Error;
+use crate::fade::Fade;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Scenes by name, stored in `scenes.toml` next to the config file.
+///
+/// ```toml
+/// [[recording]]
+/// light = "desk"
+/// ip_address = "192.168.0.25"
+///
+/// [[recording.lights]]
+/// index = 0
+/// on = true
+/// brightness = 20
+/// temperature = 4000
+/// ```
+pub type Scenes = BTreeMap<String, Vec<SceneLight>>;
+
+/// The saved state of one device in a scene.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneLight {
+    /// The name or IP address the light was targeted with when saving.
+    pub light: String,
+    pub ip_address: Address,
+    pub lights: Vec<LightState>,
+}
+
+/// Puts one device's lights back how they were saved.
+pub async fn restore(
+    scene_light: &SceneLight,
+    config: &Config,
+    fade: Option<Fade>,
+    connector: &Connector,
+) -> Result<(), Error> {
+    // Named lights are looked up again in case their address has changed since saving.
+    let address = config
+        .resolve(&scene_light.light)
+        .unwrap_or_else(|_| scene_light.ip_address.clone());
+    let operation = Operation::Restore(scene_light.lights.clone());
+
+    connector
+        .perform(&LightTarget::new(address), fade, operation)
+        .await?;
+    Ok(())
+}
+
+/// `scenes.toml` in the same directory as the config file.
+pub fn path(config_path: Option<&Path>) -> Result<PathBuf, Error> {
+    Ok(Config::path(config_path)?.with_file_name("scenes.toml"))
+}
+
+pub fn load(path: &Path) -> Result<Scenes, Error> {
+    match fs::read_to_string(path) {
+        Ok(contents) => toml::from_str(&contents)
+            .map_err(|e| Error::Config(format!("Invalid scenes file {}: {}", path.display(), e))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Scenes::new()),
+        Err(e) => Err(Error::Config(format!(
+            "Unable to read {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+pub fn save(path: &Path, scenes: &Scenes) -> Result<(), Error> {
+    let contents = toml::to_string(scenes)
+        .map_err(|e| Error::Config(format!("Unable to save the scenes: {}", e)))?;
+    config::write_file(path, &contents)
+}
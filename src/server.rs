@@ -0,0 +1,517 @@
+//! An HTTP API for controlling lights from other programs and machines.
+//!
+//! | Request                       | Does                                                 |
+//! |-------------------------------|------------------------------------------------------|
+//! | `GET /lights`                 | Lists the named lights in the config file            |
+//! | `GET /lights/{light}`         | Gets the status of the lights, like `status`         |
+//! | `PUT /lights/{light}`         | Sets any of `on`, `brightness` and `temperature`     |
+//! | `POST /lights/{light}/toggle` | Turns the lights off if any are on, and on otherwise |
+//! | `GET /scenes`                 | Lists the saved scenes                               |
+//! | `POST /scenes/{scene}/apply`  | Restores the lights to a scene                       |
+//!
+//! `{light}` is a name from the config file or the last discovery, or an IP address. Add
+//! `?index=1` to only control one light on the device. Changes return the new status of the
+//! lights, and failures return `{"error": "...", "code": 5}` with the exit code the CLI would have
+//! used.
+
+use crate::address::Address;
+use crate::config::Config;
+use crate::connector::Connector;
+use crate::controller::{LightTarget, Operation};
+use crate::error::Error;
+use crate::fade::{parse_duration, Easing, Fade};
+use crate::keylight::RequestOptions;
+use crate::output::LightStatus;
+use crate::scene::{self, SceneLight};
+use crate::temperature::Temperature;
+use futures::future::join_all;
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Answers API requests, running them against the lights with a [`Connector`].
+#[derive(Debug)]
+pub struct Server {
+    config_path: Option<PathBuf>,
+    socket: Option<PathBuf>,
+    options: RequestOptions,
+    token: Option<String>,
+}
+
+/// A named light from the config file.
+#[derive(Debug, Serialize)]
+struct NamedLight<'a> {
+    name: &'a str,
+    ip_address: &'a Address,
+    default: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct SceneSummary<'a> {
+    name: &'a str,
+    lights: Vec<&'a str>,
+}
+
+/// How one device fared when applying a scene.
+#[derive(Debug, Serialize)]
+struct SceneResult {
+    light: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<u8>,
+}
+
+/// The body of `PUT /lights/{light}`. Fields left out keep their value.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StateChange {
+    on: Option<bool>,
+    /// Brightness in percent (0-100).
+    brightness: Option<u8>,
+    /// Temperature in Kelvin (2900-7000).
+    temperature: Option<u32>,
+    fade: Option<String>,
+    easing: Option<String>,
+}
+
+/// How to fade to the new state, such as `{"fade": "500ms", "easing": "linear"}`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FadeOptions {
+    fade: Option<String>,
+    easing: Option<String>,
+}
+
+impl FadeOptions {
+    fn fade(&self) -> Result<Option<Fade>, Error> {
+        fade(self.fade.as_deref(), self.easing.as_deref())
+    }
+}
+
+fn fade(duration: Option<&str>, easing: Option<&str>) -> Result<Option<Fade>, Error> {
+    let Some(duration) = duration else {
+        return Ok(None);
+    };
+    let easing = match easing {
+        Some(easing) => easing.parse().map_err(Error::InvalidInput)?,
+        None => Easing::EaseInOut,
+    };
+
+    Ok(Some(Fade {
+        duration: parse_duration(duration).map_err(Error::InvalidInput)?,
+        easing,
+    }))
+}
+
+impl Server {
+    /// Reads the named lights and scenes from the config file at `config_path`, or the default
+    /// location, on every request so changes are picked up without a restart. Requests go through
+    /// the daemon on `socket` whenever it's running, and otherwise to the lights directly with
+    /// `options`. When `token` is set, requests must send it as `Authorization: Bearer <token>`.
+    pub fn new(
+        config_path: Option<PathBuf>,
+        socket: Option<PathBuf>,
+        options: RequestOptions,
+        token: Option<String>,
+    ) -> Server {
+        Server {
+            config_path,
+            socket,
+            options,
+            token,
+        }
+    }
+
+    /// Answers requests on `listener` until the process ends.
+    pub async fn serve(self, listener: TcpListener) -> Result<(), Error> {
+        let server = Arc::new(self);
+        let make_service = make_service_fn(move |_| {
+            let server = server.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let server = server.clone();
+                    async move { Ok::<_, Infallible>(server.handle(request).await) }
+                }))
+            }
+        });
+
+        let unable = |e: hyper::Error| Error::Other(format!("Unable to serve the API: {}", e));
+        hyper::Server::from_tcp(listener)
+            .map_err(unable)?
+            .serve(make_service)
+            .await
+            .map_err(unable)
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if !self.authorized(&request) {
+            let mut response = error(
+                StatusCode::UNAUTHORIZED,
+                "Missing or wrong token".to_string(),
+                None,
+            );
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return response;
+        }
+
+        match self.route(request).await {
+            Ok(response) => response,
+            Err(e) => error(status_code(&e), e.to_string(), Some(e.exit_code())),
+        }
+    }
+
+    fn authorized(&self, request: &Request<Body>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+
+        request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|sent| constant_time_eq(sent.as_bytes(), token.as_bytes()))
+    }
+
+    async fn route(&self, request: Request<Body>) -> Result<Response<Body>, Error> {
+        let config = Config::load(self.config_path.as_deref())?;
+        let index = index(request.uri().query())?;
+        let segments = request
+            .uri()
+            .path()
+            .trim_matches('/')
+            .split('/')
+            .map(percent_decode)
+            .collect::<Result<Vec<_>, _>>()?;
+        let method = request.method().clone();
+        let body = hyper::body::to_bytes(request.into_body())
+            .await
+            .map_err(|e| Error::InvalidInput(format!("Unable to read the request: {}", e)))?;
+
+        // Looked up on every request, so a daemon started or stopped since is picked up.
+        let connector = Connector::new(self.socket.as_deref(), self.options).await;
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        match (&method, segments.as_slice()) {
+            (&Method::GET, ["lights"]) => json(&named_lights(&config)),
+            (&Method::GET, ["lights", light]) => {
+                let target = target(light, index, &config)?;
+                json(&self.status(&connector, light, &target).await?)
+            }
+            (&Method::PUT, ["lights", light]) => {
+                let change: StateChange = parse_body(&body)?;
+                let target = target(light, index, &config)?;
+                let operation = change.operation()?;
+                connector
+                    .perform(&target, change.fade()?, operation)
+                    .await?;
+                json(&self.status(&connector, light, &target).await?)
+            }
+            (&Method::POST, ["lights", light, "toggle"]) => {
+                let fade: FadeOptions = parse_body(&body)?;
+                let target = target(light, index, &config)?;
+                connector
+                    .perform(&target, fade.fade()?, Operation::Toggle)
+                    .await?;
+                json(&self.status(&connector, light, &target).await?)
+            }
+            (&Method::GET, ["scenes"]) => {
+                let scenes = scene::load(&scene::path(self.config_path.as_deref())?)?;
+                let summaries: Vec<SceneSummary> = scenes
+                    .iter()
+                    .map(|(name, scene_lights)| SceneSummary {
+                        name,
+                        lights: scene_lights
+                            .iter()
+                            .map(|scene_light| scene_light.light.as_str())
+                            .collect(),
+                    })
+                    .collect();
+                json(&summaries)
+            }
+            (&Method::POST, ["scenes", name, "apply"]) => {
+                let fade: FadeOptions = parse_body(&body)?;
+                let scenes = scene::load(&scene::path(self.config_path.as_deref())?)?;
+                let Some(scene_lights) = scenes.get(*name) else {
+                    let e = Error::InvalidInput(format!("Unknown scene \"{}\"", name));
+                    return Ok(error(
+                        StatusCode::NOT_FOUND,
+                        e.to_string(),
+                        Some(e.exit_code()),
+                    ));
+                };
+                self.apply_scene(&connector, scene_lights, fade.fade()?, &config)
+                    .await
+            }
+            (_, ["lights"] | ["lights", _] | ["lights", _, "toggle"])
+            | (_, ["scenes"] | ["scenes", _, "apply"]) => Ok(empty(StatusCode::METHOD_NOT_ALLOWED)),
+            _ => Ok(empty(StatusCode::NOT_FOUND)),
+        }
+    }
+
+    async fn status(
+        &self,
+        connector: &Connector,
+        label: &str,
+        target: &LightTarget,
+    ) -> Result<Vec<LightStatus>, Error> {
+        let outcome = connector.perform(target, None, Operation::State).await?;
+        Ok(LightStatus::from_outcome(label, outcome))
+    }
+
+    /// Applies the scene to every device in it, answering with how each one fared.
+    async fn apply_scene(
+        &self,
+        connector: &Connector,
+        scene_lights: &[SceneLight],
+        fade: Option<Fade>,
+        config: &Config,
+    ) -> Result<Response<Body>, Error> {
+        let results = join_all(
+            scene_lights
+                .iter()
+                .map(|scene_light| scene::restore(scene_light, config, fade, connector)),
+        )
+        .await;
+
+        let failed = results.iter().any(Result::is_err);
+        let results: Vec<SceneResult> = scene_lights
+            .iter()
+            .zip(results)
+            .map(|(scene_light, result)| SceneResult {
+                light: scene_light.light.clone(),
+                error: result.err().map(|e| e.to_string()),
+            })
+            .collect();
+
+        let mut response = json(&results)?;
+        if failed {
+            *response.status_mut() = StatusCode::BAD_GATEWAY;
+        }
+        Ok(response)
+    }
+}
+
+impl StateChange {
+    fn fade(&self) -> Result<Option<Fade>, Error> {
+        fade(self.fade.as_deref(), self.easing.as_deref())
+    }
+
+    fn operation(&self) -> Result<Operation, Error> {
+        if self.on.is_none() && self.brightness.is_none() && self.temperature.is_none() {
+            return Err(Error::InvalidInput(
+                "Nothing to change. Send on, brightness or temperature".to_string(),
+            ));
+        }
+        if let Some(brightness) = self.brightness.filter(|brightness| *brightness > 100) {
+            return Err(Error::InvalidInput(format!(
+                "Brightness must be between 0 and 100, got {}",
+                brightness
+            )));
+        }
+        let temperature = self
+            .temperature
+            .map(Temperature::from_kelvin)
+            .transpose()
+            .map_err(Error::InvalidInput)?;
+
+        Ok(Operation::Change {
+            on: self.on,
+            brightness: self.brightness,
+            temperature,
+        })
+    }
+}
+
+fn named_lights(config: &Config) -> Vec<NamedLight<'_>> {
+    config
+        .lights
+        .iter()
+        .map(|(name, light)| NamedLight {
+            name,
+            ip_address: &light.ip_address,
+            default: config.default_light.as_ref() == Some(name),
+        })
+        .collect()
+}
+
+/// A light by its name, or by its IP address when no light has that name. Hostnames aren't
+/// looked up, so a mistyped name is reported as unknown and the API can only reach lights by name
+/// or IP address.
+fn target(light: &str, index: Option<usize>, config: &Config) -> Result<LightTarget, Error> {
+    let address = match config.resolve(light) {
+        Ok(address) => address,
+        Err(e) if is_ip_address(light) => light.parse().map_err(|_| e)?,
+        Err(e) => return Err(e),
+    };
+
+    Ok(LightTarget { address, index })
+}
+
+/// Whether `light` is an IP address, with or without a port.
+fn is_ip_address(light: &str) -> bool {
+    light.parse::<IpAddr>().is_ok() || light.parse::<SocketAddr>().is_ok()
+}
+
+/// The `index` query parameter.
+fn index(query: Option<&str>) -> Result<Option<usize>, Error> {
+    let Some(value) = query
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("index="))
+    else {
+        return Ok(None);
+    };
+
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| Error::InvalidInput(format!("Invalid index \"{}\"", value)))
+}
+
+/// Decodes `%20` and the like in a path segment.
+fn percent_decode(segment: &str) -> Result<String, Error> {
+    let invalid = || Error::InvalidInput(format!("Invalid path segment \"{}\"", segment));
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).ok_or_else(invalid)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Parses a JSON body, treating an empty one as `{}`.
+fn parse_body<T: DeserializeOwned + Default>(body: &[u8]) -> Result<T, Error> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+    serde_json::from_slice(body)
+        .map_err(|e| Error::InvalidInput(format!("Invalid request body: {}", e)))
+}
+
+/// Compares the token without stopping at the first difference, so the time taken doesn't give
+/// away how much of it was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// The HTTP status for an error. It goes by the exit code, so errors passed on from the daemon
+/// map the same way.
+fn status_code(e: &Error) -> StatusCode {
+    match e.exit_code() {
+        // An unknown light.
+        2 => StatusCode::NOT_FOUND,
+        3 => StatusCode::BAD_REQUEST,
+        6 => StatusCode::GATEWAY_TIMEOUT,
+        5 | 7 | 8 | 9 => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn json<T: Serialize>(body: &T) -> Result<Response<Body>, Error> {
+    let body = serde_json::to_vec(body)
+        .map_err(|e| Error::Other(format!("Unable to write the response: {}", e)))?;
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("the response is valid"))
+}
+
+fn error(status: StatusCode, message: String, code: Option<u8>) -> Response<Body> {
+    let body = serde_json::to_vec(&ErrorBody {
+        error: message,
+        code,
+    })
+    .expect("the error serializes");
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("the response is valid")
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("the response is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_path_segments() {
+        assert_eq!(percent_decode("desk").unwrap(), "desk");
+        assert_eq!(percent_decode("desk%20key").unwrap(), "desk key");
+        assert_eq!(percent_decode("%5B::1%5D:80").unwrap(), "[::1]:80");
+        assert!(percent_decode("desk%2").is_err());
+        assert!(percent_decode("desk%zz").is_err());
+    }
+
+    #[test]
+    fn only_looks_up_names_and_ip_addresses() {
+        let config = Config::default();
+        let address = |light| target(light, None, &config).map(|target| target.address);
+
+        assert_eq!(
+            address("192.168.0.25").unwrap(),
+            "192.168.0.25".parse().unwrap()
+        );
+        assert_eq!(address("[::1]:80").unwrap(), "[::1]:80".parse().unwrap());
+        let error = address("desk-typo").unwrap_err();
+        assert_eq!(error.exit_code(), 2);
+        assert_eq!(status_code(&error), StatusCode::NOT_FOUND);
+        assert!(address("keylight.local").is_err());
+    }
+
+    #[test]
+    fn reads_the_index_from_the_query() {
+        assert_eq!(index(None).unwrap(), None);
+        assert_eq!(index(Some("index=1")).unwrap(), Some(1));
+        assert_eq!(index(Some("fade=1s&index=0")).unwrap(), Some(0));
+        assert!(index(Some("index=-1")).is_err());
+    }
+
+    #[test]
+    fn only_accepts_valid_changes() {
+        let change = |json: &str| parse_body::<StateChange>(json.as_bytes())?.operation();
+
+        assert_eq!(
+            change(r#"{"on": false}"#).unwrap(),
+            Operation::Change {
+                on: Some(false),
+                brightness: None,
+                temperature: None,
+            }
+        );
+        assert!(change("{}").is_err());
+        assert!(change(r#"{"brightness": 101}"#).is_err());
+        assert!(change(r#"{"temperature": 9000}"#).is_err());
+        assert!(change(r#"{"colour": "red"}"#).is_err());
+    }
+}
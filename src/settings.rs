@@ -0,0 +1,171 @@
+use crate::fade::parse_duration;
+use crate::keylight::Settings;
+use crate::temperature::Temperature;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The longest switch-on or switch-off duration a light is set to.
+pub const MAX_SWITCH_DURATION: Duration = Duration::from_secs(10);
+
+/// Parses a switch-on or switch-off duration, which the light stores as a whole number of
+/// milliseconds up to [`MAX_SWITCH_DURATION`].
+pub fn parse_switch_duration(s: &str) -> Result<Duration, String> {
+    let duration = parse_duration(s)?;
+    if duration.subsec_nanos() % 1_000_000 != 0 {
+        return Err(format!(
+            "The duration must be a whole number of milliseconds, got {}",
+            s
+        ));
+    }
+    if duration > MAX_SWITCH_DURATION {
+        return Err(format!("The duration must be at most 10s, got {}", s));
+    }
+    Ok(duration)
+}
+
+/// What a light does when it gets power back, such as after a power outage.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PowerOnBehavior {
+    /// Comes back in the state it was in before losing power.
+    Restore,
+    /// Comes back on with the power-on brightness and temperature.
+    Default,
+    /// A value this tool doesn't know about.
+    Other(u8),
+}
+
+impl PowerOnBehavior {
+    pub fn from_device(value: u8) -> PowerOnBehavior {
+        match value {
+            1 => PowerOnBehavior::Restore,
+            2 => PowerOnBehavior::Default,
+            value => PowerOnBehavior::Other(value),
+        }
+    }
+
+    pub fn to_device(self) -> u8 {
+        match self {
+            PowerOnBehavior::Restore => 1,
+            PowerOnBehavior::Default => 2,
+            PowerOnBehavior::Other(value) => value,
+        }
+    }
+}
+
+impl FromStr for PowerOnBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "restore" => Ok(PowerOnBehavior::Restore),
+            "default" => Ok(PowerOnBehavior::Default),
+            _ => Err(format!(
+                "Unknown power-on behavior \"{}\". Use restore or default",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PowerOnBehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerOnBehavior::Restore => write!(f, "restore"),
+            PowerOnBehavior::Default => write!(f, "default"),
+            PowerOnBehavior::Other(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// The settings to change. Fields left as `None` keep their current value.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SettingsChange {
+    pub power_on_behavior: Option<PowerOnBehavior>,
+    pub power_on_brightness: Option<u8>,
+    pub power_on_temperature: Option<Temperature>,
+    pub switch_on_duration: Option<Duration>,
+    pub switch_off_duration: Option<Duration>,
+}
+
+impl SettingsChange {
+    pub fn is_empty(&self) -> bool {
+        *self == SettingsChange::default()
+    }
+
+    pub fn apply(&self, settings: &mut Settings) {
+        if let Some(behavior) = self.power_on_behavior {
+            settings.power_on_behavior = behavior.to_device();
+        }
+        if let Some(brightness) = self.power_on_brightness {
+            settings.power_on_brightness = brightness;
+        }
+        if let Some(temperature) = self.power_on_temperature {
+            settings.power_on_temperature = temperature.to_device();
+        }
+        if let Some(duration) = self.switch_on_duration {
+            settings.switch_on_duration_ms = millis(duration);
+        }
+        if let Some(duration) = self.switch_off_duration {
+            settings.switch_off_duration_ms = millis(duration);
+        }
+    }
+}
+
+fn millis(duration: Duration) -> u32 {
+    u32::try_from(duration.as_millis()).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_on_behavior_round_trips_through_the_device_value() {
+        for value in 0..=3 {
+            assert_eq!(PowerOnBehavior::from_device(value).to_device(), value);
+        }
+        assert_eq!("restore".parse(), Ok(PowerOnBehavior::Restore));
+        assert_eq!("default".parse(), Ok(PowerOnBehavior::Default));
+        assert!("off".parse::<PowerOnBehavior>().is_err());
+    }
+
+    #[test]
+    fn switch_durations_are_whole_milliseconds_up_to_the_maximum() {
+        assert_eq!(parse_switch_duration("0"), Ok(Duration::ZERO));
+        assert_eq!(
+            parse_switch_duration("150ms"),
+            Ok(Duration::from_millis(150))
+        );
+        assert_eq!(parse_switch_duration("10s"), Ok(MAX_SWITCH_DURATION));
+        assert!(parse_switch_duration("0.5ms").is_err());
+        assert!(parse_switch_duration("10001ms").is_err());
+        assert!(parse_switch_duration("1000m").is_err());
+    }
+
+    #[test]
+    fn changes_only_the_given_settings() {
+        let mut settings = Settings {
+            power_on_behavior: 1,
+            power_on_brightness: 20,
+            power_on_temperature: 213,
+            switch_on_duration_ms: 100,
+            switch_off_duration_ms: 300,
+            ..Default::default()
+        };
+        let change = SettingsChange {
+            power_on_behavior: Some(PowerOnBehavior::Default),
+            power_on_temperature: Some(Temperature::from_kelvin(5000).unwrap()),
+            switch_off_duration: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        change.apply(&mut settings);
+
+        assert_eq!(settings.power_on_behavior, 2);
+        assert_eq!(settings.power_on_brightness, 20);
+        assert_eq!(settings.power_on_temperature, 200);
+        assert_eq!(settings.switch_on_duration_ms, 100);
+        assert_eq!(settings.switch_off_duration_ms, 1000);
+    }
+}
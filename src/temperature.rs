@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+pub const MIN_KELVIN: u32 = 2900;
+pub const MAX_KELVIN: u32 = 7000;
+
+/// The device's own temperature range, in mireds (one million divided by the Kelvin value).
+pub const MIN_DEVICE: u16 = 143;
+pub const MAX_DEVICE: u16 = 344;
+
+/// A color temperature in Kelvin, always within the range the lights support.
+///
+/// The lights work in mireds, which only have a resolution of about 8-49 K across the range, so
+/// a value converted to the device and back may come out a few Kelvin off. Converting a device
+/// value to Kelvin and back always gives the same device value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct Temperature(u32);
+
+impl Temperature {
+    pub fn from_kelvin(kelvin: u32) -> Result<Temperature, String> {
+        if (MIN_KELVIN..=MAX_KELVIN).contains(&kelvin) {
+            Ok(Temperature(kelvin))
+        } else {
+            Err(format!(
+                "Temperature must be between {} and {} Kelvin, got {}",
+                MIN_KELVIN, MAX_KELVIN, kelvin
+            ))
+        }
+    }
+
+    /// Converts a temperature reported by a device, clamping it to the supported range.
+    pub fn from_device(value: u16) -> Temperature {
+        let mireds = value.clamp(MIN_DEVICE, MAX_DEVICE);
+        let kelvin = (1_000_000.0 / f64::from(mireds)).round() as u32;
+        Temperature(kelvin.clamp(MIN_KELVIN, MAX_KELVIN))
+    }
+
+    /// Adds `delta` Kelvin, clamping the result to the supported range.
+    pub fn saturating_add(self, delta: i32) -> Temperature {
+        let kelvin = i64::from(self.0) + i64::from(delta);
+        Temperature(kelvin.clamp(i64::from(MIN_KELVIN), i64::from(MAX_KELVIN)) as u32)
+    }
+
+    pub fn kelvin(self) -> u32 {
+        self.0
+    }
+
+    pub fn to_device(self) -> u16 {
+        let mireds = (1_000_000.0 / f64::from(self.0)).round() as u16;
+        mireds.clamp(MIN_DEVICE, MAX_DEVICE)
+    }
+}
+
+/// A warm white, used when neither the command line nor the config sets a temperature.
+impl Default for Temperature {
+    fn default() -> Self {
+        Temperature(3000)
+    }
+}
+
+impl TryFrom<u32> for Temperature {
+    type Error = String;
+
+    fn try_from(kelvin: u32) -> Result<Self, Self::Error> {
+        Temperature::from_kelvin(kelvin)
+    }
+}
+
+impl From<Temperature> for u32 {
+    fn from(temperature: Temperature) -> u32 {
+        temperature.0
+    }
+}
+
+impl FromStr for Temperature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kelvin = s
+            .trim_end_matches(['K', 'k'])
+            .parse()
+            .map_err(|_| format!("Invalid temperature \"{}\"", s))?;
+        Temperature::from_kelvin(kelvin)
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}K", self.0)
+    }
+}
+
+/// A temperature from the command line: a Kelvin value like `5000`, or a change like `+200` or
+/// `-500`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemperatureChange {
+    pub kelvin: i32,
+    pub signed: bool,
+}
+
+impl TemperatureChange {
+    /// What the change does. Signed values are relative unless `absolute` is set, and bare values
+    /// are absolute unless `relative` is set.
+    pub fn adjustment(
+        self,
+        absolute: bool,
+        relative: bool,
+    ) -> Result<TemperatureAdjustment, String> {
+        if relative || (self.signed && !absolute) {
+            return Ok(TemperatureAdjustment::By(self.kelvin));
+        }
+
+        let kelvin = u32::try_from(self.kelvin)
+            .map_err(|_| format!("Invalid temperature \"{}\"", self.kelvin))?;
+        Temperature::from_kelvin(kelvin).map(TemperatureAdjustment::Set)
+    }
+
+    /// Applies the change to `current`, see [`TemperatureChange::adjustment`].
+    pub fn apply(
+        self,
+        current: Temperature,
+        absolute: bool,
+        relative: bool,
+    ) -> Result<Temperature, String> {
+        Ok(self.adjustment(absolute, relative)?.apply(current))
+    }
+}
+
+/// A change to a light's temperature: to a set value, or by a number of Kelvin.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TemperatureAdjustment {
+    Set(Temperature),
+    By(i32),
+}
+
+impl TemperatureAdjustment {
+    /// Applies the change to `current`, clamping the result to the supported range.
+    pub fn apply(self, current: Temperature) -> Temperature {
+        match self {
+            TemperatureAdjustment::Set(temperature) => temperature,
+            TemperatureAdjustment::By(delta) => current.saturating_add(delta),
+        }
+    }
+}
+
+impl FromStr for TemperatureChange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kelvin = s
+            .trim_end_matches(['K', 'k'])
+            .parse()
+            .map_err(|_| format!("Invalid temperature \"{}\"", s))?;
+        Ok(TemperatureChange {
+            kelvin,
+            signed: s.starts_with(['+', '-']),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_kelvin_outside_the_supported_range() {
+        assert!(Temperature::from_kelvin(2899).is_err());
+        assert!(Temperature::from_kelvin(7001).is_err());
+        assert!("10000".parse::<Temperature>().is_err());
+        assert_eq!("2900".parse(), Ok(Temperature(2900)));
+        assert_eq!("7000K".parse(), Ok(Temperature(7000)));
+    }
+
+    #[test]
+    fn converts_range_ends_to_device_range_ends() {
+        assert_eq!(Temperature(MIN_KELVIN).to_device(), MAX_DEVICE);
+        assert_eq!(Temperature(MAX_KELVIN).to_device(), MIN_DEVICE);
+        assert_eq!(Temperature::from_device(MIN_DEVICE).kelvin(), 6993);
+        assert_eq!(Temperature::from_device(MAX_DEVICE).kelvin(), 2907);
+    }
+
+    #[test]
+    fn device_values_survive_a_round_trip() {
+        for value in MIN_DEVICE..=MAX_DEVICE {
+            assert_eq!(Temperature::from_device(value).to_device(), value);
+        }
+    }
+
+    #[test]
+    fn applies_signed_changes_relative_to_the_current_temperature() {
+        let current = Temperature(5000);
+        let change = |s: &str| s.parse::<TemperatureChange>().unwrap();
+
+        assert_eq!(
+            change("+200").apply(current, false, false),
+            Ok(Temperature(5200))
+        );
+        assert_eq!(
+            change("-500").apply(current, false, false),
+            Ok(Temperature(4500))
+        );
+        assert_eq!(
+            change("-5000").apply(current, false, false),
+            Ok(Temperature(2900))
+        );
+        assert_eq!(
+            change("200").apply(current, false, true),
+            Ok(Temperature(5200))
+        );
+        assert_eq!(
+            change("4000").apply(current, false, false),
+            Ok(Temperature(4000))
+        );
+        assert_eq!(
+            change("+4000").apply(current, true, false),
+            Ok(Temperature(4000))
+        );
+        assert!(change("-4000").apply(current, true, false).is_err());
+    }
+
+    #[test]
+    fn clamps_device_values_outside_the_range() {
+        assert_eq!(Temperature::from_device(0).kelvin(), 6993);
+        assert_eq!(Temperature::from_device(1000).kelvin(), 2907);
+    }
+}
@@ -0,0 +1,801 @@
+//! Runs each subcommand of the binary against a mock light.
+
+use elgato_light::keylight::Light;
+use elgato_light::mock::{unused_port, MockLight};
+use elgato_light::temperature::Temperature;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::process::{Output, Stdio};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+
+/// Runs the binary with its config, scenes, discovery cache and daemon socket in a directory of
+/// its own.
+struct Cli {
+    home: PathBuf,
+}
+
+impl Cli {
+    fn new(test: &str) -> Cli {
+        let home =
+            std::env::temp_dir().join(format!("elgato-light-test-{}-{}", std::process::id(), test));
+        let _ = std::fs::remove_dir_all(&home);
+        std::fs::create_dir_all(&home).unwrap();
+        Cli { home }
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_elgato-light"));
+        command
+            .args(args)
+            .env("HOME", &self.home)
+            .env("XDG_CONFIG_HOME", self.home.join("config"))
+            .env("XDG_CACHE_HOME", self.home.join("cache"))
+            .env("XDG_RUNTIME_DIR", self.home.join("run"))
+            .env_remove("ELGATO_LIGHT_CONFIG")
+            .env_remove("ELGATO_LIGHT_SOCKET");
+        command
+    }
+
+    async fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().await.unwrap()
+    }
+
+    /// Starts the daemon, returning once it's listening. It's stopped when dropped.
+    #[cfg(unix)]
+    async fn daemon(&self) -> Child {
+        self.spawn(&["daemon"]).await.0
+    }
+
+    /// Starts a long-running command, returning it and what it's listening on once it has
+    /// printed that. It's stopped when dropped.
+    async fn spawn(&self, args: &[&str]) -> (Child, String) {
+        let mut child = self
+            .command(args)
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let line = BufReader::new(stdout).lines().next_line().await.unwrap();
+        let listening = line
+            .unwrap()
+            .strip_prefix("Listening on ")
+            .unwrap()
+            .to_string();
+        (child, listening)
+    }
+
+    /// Runs a command that should succeed, returning what it printed.
+    async fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args).await;
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Runs a command that should fail, returning its exit code.
+    async fn fails(&self, args: &[&str]) -> i32 {
+        let output = self.run(args).await;
+        assert!(!output.status.success(), "{:?} succeeded", args);
+        output.status.code().unwrap()
+    }
+
+    async fn json(&self, args: &[&str]) -> Value {
+        serde_json::from_str(&self.ok(args).await).unwrap()
+    }
+}
+
+impl Drop for Cli {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.home);
+    }
+}
+
+fn address(light: &MockLight) -> String {
+    light.address().to_string()
+}
+
+fn device_temperature(kelvin: u32) -> u16 {
+    Temperature::from_kelvin(kelvin).unwrap().to_device()
+}
+
+fn light_state(on: u8, brightness: u8, kelvin: u32) -> Light {
+    Light {
+        on,
+        brightness,
+        temperature: device_temperature(kelvin),
+    }
+}
+
+#[tokio::test]
+async fn on_sets_the_brightness_and_temperature() {
+    let cli = Cli::new("on");
+    let light = MockLight::start(2).await.unwrap();
+
+    cli.ok(&["on", "-i", &address(&light), "-b", "40", "-t", "5000"])
+        .await;
+
+    assert_eq!(light.lights(), vec![light_state(1, 40, 5000); 2]);
+}
+
+#[tokio::test]
+async fn on_uses_the_brightness_and_temperature_of_the_named_light() {
+    let cli = Cli::new("on-named");
+    let light = MockLight::start(1).await.unwrap();
+
+    cli.ok(&[
+        "light",
+        "add",
+        "desk",
+        &address(&light),
+        "-b",
+        "35",
+        "-t",
+        "4000",
+        "--default",
+    ])
+    .await;
+    cli.ok(&["on"]).await;
+
+    assert_eq!(light.lights(), vec![light_state(1, 35, 4000)]);
+}
+
+#[tokio::test]
+async fn off_turns_off_only_the_selected_light() {
+    let cli = Cli::new("off");
+    let light = MockLight::start(2).await.unwrap();
+    light.set_light(0, light_state(1, 50, 5000));
+    light.set_light(1, light_state(1, 50, 5000));
+
+    cli.ok(&["off", "-i", &address(&light), "--index", "1"])
+        .await;
+
+    let lights = light.lights();
+    assert_eq!(lights[0].on, 1);
+    assert_eq!(lights[1], light_state(0, 50, 5000));
+}
+
+#[tokio::test]
+async fn toggle_turns_the_lights_off_when_any_is_on_and_back_on() {
+    let cli = Cli::new("toggle");
+    let light = MockLight::start(2).await.unwrap();
+    light.set_light(1, light_state(1, 60, 4000));
+
+    cli.ok(&["toggle", "-i", &address(&light)]).await;
+    assert!(light.lights().iter().all(|light| light.on == 0));
+
+    cli.ok(&["toggle", "-i", &address(&light)]).await;
+    assert!(light.lights().iter().all(|light| light.on == 1));
+    assert_eq!(light.lights()[1], light_state(1, 60, 4000));
+}
+
+#[tokio::test]
+async fn brightness_changes_or_sets_the_brightness() {
+    let cli = Cli::new("brightness");
+    let light = MockLight::start(1).await.unwrap();
+    let address = address(&light);
+    let brightness = || light.lights()[0].brightness;
+
+    cli.ok(&["brightness", "-i", &address, "15"]).await;
+    assert_eq!(brightness(), 35);
+    assert_eq!(light.lights()[0].on, 1);
+
+    cli.ok(&["brightness", "-i", &address, "--", "-50"]).await;
+    assert_eq!(brightness(), 0);
+
+    cli.ok(&["brightness", "-i", &address, "--set", "80"]).await;
+    assert_eq!(brightness(), 80);
+
+    cli.ok(&["brightness", "-i", &address, "--up", "--step", "5"])
+        .await;
+    assert_eq!(brightness(), 85);
+
+    cli.ok(&["brightness", "-i", &address, "--absolute", "10"])
+        .await;
+    assert_eq!(brightness(), 10);
+
+    cli.ok(&["brightness", "-i", &address, "--down"]).await;
+    assert_eq!(brightness(), 0);
+
+    cli.fails(&["brightness", "-i", &address, "--up", "--absolute"])
+        .await;
+    cli.fails(&[
+        "brightness",
+        "-i",
+        &address,
+        "--step",
+        "5",
+        "--relative",
+        "5",
+    ])
+    .await;
+    assert_eq!(brightness(), 0);
+}
+
+#[tokio::test]
+async fn temperature_sets_or_changes_the_temperature() {
+    let cli = Cli::new("temperature");
+    let light = MockLight::start(1).await.unwrap();
+    let address = address(&light);
+
+    cli.ok(&["temperature", "-i", &address, "5000"]).await;
+    assert_eq!(light.lights()[0].temperature, device_temperature(5000));
+
+    cli.ok(&["temperature", "-i", &address, "+1000"]).await;
+    assert_eq!(light.lights()[0].temperature, device_temperature(6000));
+
+    assert_eq!(cli.fails(&["temperature", "-i", &address, "9000"]).await, 3);
+    assert_eq!(light.lights()[0].temperature, device_temperature(6000));
+}
+
+#[tokio::test]
+async fn fades_end_at_the_new_state() {
+    let cli = Cli::new("fade");
+    let light = MockLight::start(1).await.unwrap();
+
+    cli.ok(&[
+        "on",
+        "-i",
+        &address(&light),
+        "-b",
+        "80",
+        "-t",
+        "3000",
+        "--fade",
+        "200ms",
+    ])
+    .await;
+
+    assert_eq!(light.lights(), vec![light_state(1, 80, 3000)]);
+}
+
+#[tokio::test]
+async fn status_reports_each_light() {
+    let cli = Cli::new("status");
+    let light = MockLight::start(2).await.unwrap();
+    light.set_light(1, light_state(1, 70, 5000));
+
+    let status = cli
+        .json(&["status", "-i", &address(&light), "-o", "json"])
+        .await;
+
+    assert_eq!(status.as_array().unwrap().len(), 2);
+    assert_eq!(status[1]["light"], address(&light));
+    assert_eq!(status[1]["name"], "Elgato Key Light");
+    assert_eq!(status[1]["index"], 1);
+    assert_eq!(status[1]["on"], true);
+    assert_eq!(status[1]["brightness"], 70);
+    assert_eq!(status[1]["temperature"], 5000);
+    assert_eq!(status[0]["on"], false);
+
+    let plain = cli
+        .ok(&["status", "-i", &address(&light), "--index", "1"])
+        .await;
+    assert_eq!(
+        plain,
+        format!(
+            "{} (Elgato Key Light) [1]: on, 70%, 5000K\n",
+            address(&light)
+        )
+    );
+}
+
+#[tokio::test]
+async fn info_shows_the_accessory_info() {
+    let cli = Cli::new("info");
+    let light = MockLight::start(1).await.unwrap();
+
+    let info = cli
+        .json(&["info", "-i", &address(&light), "-o", "json"])
+        .await;
+
+    assert_eq!(info[0]["product_name"], "Elgato Key Light");
+    assert_eq!(info[0]["serial_number"], "BW33J1A02740");
+    assert_eq!(info[0]["firmware_version"], "1.0.3");
+}
+
+#[tokio::test]
+async fn identify_asks_the_device_to_flash() {
+    let cli = Cli::new("identify");
+    let light = MockLight::start(1).await.unwrap();
+
+    cli.ok(&["identify", "-i", &address(&light)]).await;
+
+    assert_eq!(light.identified(), 1);
+}
+
+#[tokio::test]
+async fn identify_pulses_lights_without_an_identify_endpoint() {
+    let cli = Cli::new("identify-pulse");
+    let light = MockLight::start(1).await.unwrap();
+    light.set_identify(false);
+    let before = light.lights();
+
+    cli.ok(&["identify", "-i", &address(&light)]).await;
+
+    assert_eq!(light.identified(), 0);
+    assert_eq!(light.lights(), before);
+}
+
+#[tokio::test]
+async fn rename_sets_the_name_shown_in_status() {
+    let cli = Cli::new("rename");
+    let light = MockLight::start(1).await.unwrap();
+
+    cli.ok(&["rename", "-i", &address(&light), "Desk Key Light"])
+        .await;
+
+    assert_eq!(light.accessory_info().display_name, "Desk Key Light");
+    let status = cli
+        .json(&["status", "-i", &address(&light), "-o", "json"])
+        .await;
+    assert_eq!(status[0]["name"], "Desk Key Light");
+}
+
+#[tokio::test]
+async fn settings_are_read_and_changed() {
+    let cli = Cli::new("settings");
+    let light = MockLight::start(1).await.unwrap();
+    let address = address(&light);
+
+    let settings = cli
+        .json(&["settings", "get", "-i", &address, "-o", "json"])
+        .await;
+    assert_eq!(settings[0]["power_on_behavior"], "restore");
+    assert_eq!(settings[0]["power_on_brightness"], 20);
+
+    cli.ok(&[
+        "settings",
+        "set",
+        "-i",
+        &address,
+        "--power-on-behavior",
+        "default",
+        "--power-on-temperature",
+        "5000",
+        "--switch-on-duration",
+        "1s",
+    ])
+    .await;
+
+    let settings = light.settings();
+    assert_eq!(settings.power_on_behavior, 2);
+    assert_eq!(settings.power_on_brightness, 20);
+    assert_eq!(settings.power_on_temperature, device_temperature(5000));
+    assert_eq!(settings.switch_on_duration_ms, 1000);
+    assert!(settings.other.contains_key("colorChangeDurationMs"));
+}
+
+#[tokio::test]
+async fn scenes_are_saved_applied_and_removed() {
+    let cli = Cli::new("scene");
+    let light = MockLight::start(2).await.unwrap();
+    light.set_light(0, light_state(1, 30, 3200));
+    let saved = light.lights();
+
+    cli.ok(&["scene", "save", "evening", "-i", &address(&light)])
+        .await;
+    assert!(cli.ok(&["scene", "list"]).await.starts_with("evening\t"));
+
+    light.set_light(0, light_state(0, 90, 6500));
+    light.set_light(1, light_state(1, 90, 6500));
+    cli.ok(&["scene", "apply", "evening"]).await;
+    assert_eq!(light.lights(), saved);
+
+    cli.ok(&["scene", "remove", "evening"]).await;
+    assert_eq!(cli.ok(&["scene", "list"]).await, "");
+    assert_ne!(cli.fails(&["scene", "apply", "evening"]).await, 0);
+}
+
+#[tokio::test]
+async fn named_lights_are_added_listed_and_removed() {
+    let cli = Cli::new("light");
+    let light = MockLight::start(1).await.unwrap();
+
+    cli.ok(&["light", "add", "desk", &address(&light), "--default"])
+        .await;
+    assert_eq!(
+        cli.ok(&["light", "list"]).await,
+        format!("desk\t{} (default)\n", address(&light))
+    );
+
+    cli.ok(&["off", "-l", "desk"]).await;
+
+    cli.ok(&["light", "remove", "desk"]).await;
+    assert_eq!(cli.ok(&["light", "list"]).await, "");
+    assert_eq!(cli.fails(&["off", "-l", "desk"]).await, 2);
+}
+
+#[tokio::test]
+async fn discover_stops_after_the_duration() {
+    let cli = Cli::new("discover");
+
+    cli.ok(&["discover", "--duration", "200ms"]).await;
+}
+
+#[tokio::test]
+async fn discovering_nothing_keeps_the_last_lights() {
+    let cli = Cli::new("discover-nothing");
+    let light = MockLight::start(1).await.unwrap();
+    let cache = cli.home.join("cache/elgato-light/discovered.toml");
+    std::fs::create_dir_all(cache.parent().unwrap()).unwrap();
+    std::fs::write(
+        &cache,
+        format!(
+            "[[lights]]\nname = \"Key Light\"\nip_address = \"{}\"\nport = {}\n",
+            light.address().ip(),
+            light.address().port()
+        ),
+    )
+    .unwrap();
+
+    cli.ok(&["discover", "--duration", "200ms"]).await;
+
+    cli.ok(&["on", "-l", "key light", "-b", "30"]).await;
+    assert_eq!(light.lights()[0].brightness, 30);
+}
+
+#[tokio::test]
+async fn several_lights_are_controlled_at_once() {
+    let cli = Cli::new("several");
+    let first = MockLight::start(1).await.unwrap();
+    let second = MockLight::start(2).await.unwrap();
+
+    cli.ok(&[
+        "on",
+        "-i",
+        &address(&first),
+        "-i",
+        &address(&second),
+        "-b",
+        "25",
+        "-t",
+        "3000",
+    ])
+    .await;
+
+    assert_eq!(first.lights(), vec![light_state(1, 25, 3000)]);
+    assert_eq!(second.lights(), vec![light_state(1, 25, 3000); 2]);
+}
+
+#[tokio::test]
+async fn ipv6_addresses_keep_their_zone() {
+    let cli = Cli::new("ipv6-zone");
+    let light = MockLight::bind("[::1]:0".parse().unwrap(), 1)
+        .await
+        .unwrap();
+
+    // The loopback interface is the first one, and a zone is allowed on any IPv6 address.
+    cli.ok(&[
+        "on",
+        "-i",
+        &format!("[::1%1]:{}", light.address().port()),
+        "-b",
+        "35",
+    ])
+    .await;
+
+    assert_eq!(light.lights()[0].brightness, 35);
+}
+
+#[tokio::test]
+async fn failures_exit_with_their_code() {
+    let cli = Cli::new("failures");
+    let light = MockLight::start(1).await.unwrap();
+    let unreachable = format!("127.0.0.1:{}", unused_port());
+
+    assert_eq!(cli.fails(&["on", "-i", "192.168.0"]).await, 2);
+    assert_eq!(
+        cli.fails(&["on", "-i", &address(&light), "--index", "1"])
+            .await,
+        3
+    );
+    assert_eq!(
+        cli.fails(&["on", "--retries", "0", "-i", &unreachable])
+            .await,
+        5
+    );
+    assert_eq!(
+        cli.fails(&[
+            "on",
+            "--retries",
+            "0",
+            "-i",
+            &address(&light),
+            "-i",
+            &unreachable
+        ])
+        .await,
+        9
+    );
+    assert_eq!(light.lights()[0].on, 1);
+
+    // The lights that answered are still shown when others fail.
+    let reachable = address(&light);
+    for command in [&["status"][..], &["info"], &["settings", "get"]] {
+        let mut args = command.to_vec();
+        args.extend(["--retries", "0", "-i", &reachable, "-i", &unreachable]);
+        let output = cli.run(&args).await;
+        assert_eq!(output.status.code(), Some(9), "{:?}", args);
+        assert!(
+            String::from_utf8_lossy(&output.stdout).contains(&reachable),
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn commands_use_the_daemon_while_it_runs() {
+    let cli = Cli::new("daemon");
+    let light = MockLight::start(2).await.unwrap();
+    cli.ok(&["light", "add", "desk", &address(&light), "--default"])
+        .await;
+    let mut daemon = cli.daemon().await;
+
+    cli.ok(&["on", "-b", "60", "-t", "4000"]).await;
+    assert_eq!(light.lights(), vec![light_state(1, 60, 4000); 2]);
+
+    // The daemon is already connected, so toggling only reads and writes the lights.
+    let requests = light.requests();
+    cli.ok(&["toggle", "--index", "1"]).await;
+    assert_eq!(light.requests(), requests + 2);
+    assert_eq!(light.lights()[1].on, 0);
+
+    let status = cli.json(&["status", "-o", "json"]).await;
+    assert_eq!(status[0]["on"], true);
+    assert_eq!(status[1]["on"], false);
+    assert_eq!(cli.fails(&["off", "--index", "2"]).await, 3);
+
+    // The daemon keeps the accessory info from the first status, so later ones only read the
+    // lights and info needs no request at all.
+    let requests = light.requests();
+    cli.ok(&["status"]).await;
+    let info = cli.json(&["info", "-o", "json"]).await;
+    assert_eq!(info[0]["serial_number"], "BW33J1A02740");
+    assert_eq!(light.requests(), requests + 1);
+
+    // Settings go through the daemon too: a read and a write to change them, and a read to get
+    // them.
+    let requests = light.requests();
+    cli.ok(&["settings", "set", "--power-on-brightness", "35"])
+        .await;
+    let settings = cli.json(&["settings", "get", "-o", "json"]).await;
+    assert_eq!(settings[0]["power_on_brightness"], 35);
+    assert_eq!(light.requests(), requests + 3);
+
+    // Without the daemon, commands connect to the light themselves again.
+    daemon.kill().await.unwrap();
+    daemon.wait().await.unwrap();
+    cli.ok(&["off"]).await;
+    assert!(light.lights().iter().all(|light| light.on == 0));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn only_one_daemon_runs_at_a_time() {
+    let cli = Cli::new("daemon-twice");
+    let _daemon = cli.daemon().await;
+
+    let output = cli.run(&["daemon"]).await;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already running"));
+}
+
+#[tokio::test]
+async fn serve_answers_api_requests() {
+    let cli = Cli::new("serve");
+    let light = MockLight::start(2).await.unwrap();
+    cli.ok(&["light", "add", "desk", &address(&light)]).await;
+    let (_server, url) = cli
+        .spawn(&["serve", "--bind", "127.0.0.1:0", "--token", "secret"])
+        .await;
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("{}{}", url, path)).bearer_auth("secret");
+
+    let response = client.get(format!("{}/lights", url)).send().await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    let lights: Value = get("/lights").send().await.unwrap().json().await.unwrap();
+    assert_eq!(lights[0]["name"], "desk");
+
+    let response = client
+        .put(format!("{}/lights/desk?index=1", url))
+        .bearer_auth("secret")
+        .json(&serde_json::json!({ "on": true, "brightness": 45 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let status: Value = response.json().await.unwrap();
+    assert_eq!(status[0]["index"], 1);
+    assert_eq!(status[0]["brightness"], 45);
+    assert_eq!(light.lights()[1], light_state(1, 45, 4695));
+    assert_eq!(light.lights()[0].on, 0);
+
+    let status: Value = client
+        .post(format!("{}/lights/desk/toggle", url))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status[1]["on"], false);
+
+    let response = client
+        .put(format!("{}/lights/desk", url))
+        .bearer_auth("secret")
+        .json(&serde_json::json!({ "temperature": 9000 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["code"], 3);
+
+    light.set_light(0, light_state(1, 30, 3200));
+    cli.ok(&["scene", "save", "evening", "-l", "desk"]).await;
+    light.set_light(0, light_state(0, 90, 6500));
+    let response = client
+        .post(format!("{}/scenes/evening/apply", url))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(light.lights()[0], light_state(1, 30, 3200));
+
+    let response = client
+        .post(format!("{}/scenes/missing/apply", url))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(
+        error["code"],
+        cli.fails(&["scene", "apply", "missing"]).await
+    );
+
+    // An unknown name isn't looked up as a hostname.
+    let response = get("/lights/desk-typo").send().await.unwrap();
+    assert_eq!(response.status(), 404);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["code"], 2);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serve_uses_the_daemon_whenever_it_runs() {
+    let cli = Cli::new("serve-daemon");
+    let light = MockLight::start(1).await.unwrap();
+    cli.ok(&["light", "add", "desk", &address(&light)]).await;
+    let (_server, url) = cli.spawn(&["serve", "--bind", "127.0.0.1:0"]).await;
+    let status = || async {
+        reqwest::get(format!("{}/lights/desk", url))
+            .await
+            .unwrap()
+            .status()
+    };
+
+    // A daemon started after the server is used: once it has the light, reading the status only
+    // reads the lights.
+    let mut daemon = cli.daemon().await;
+    assert_eq!(status().await, 200);
+    let requests = light.requests();
+    assert_eq!(status().await, 200);
+    assert_eq!(light.requests(), requests + 1);
+
+    // Once it stops, the server goes to the light directly again.
+    daemon.kill().await.unwrap();
+    daemon.wait().await.unwrap();
+    assert_eq!(status().await, 200);
+}
+
+/// Waits for a message on `topic` that `matches`, returning its payload.
+async fn mqtt_message(
+    events: &mut rumqttc::EventLoop,
+    topic: &str,
+    matches: impl Fn(&Value) -> bool,
+) -> Value {
+    let wait = async {
+        loop {
+            if let rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish)) =
+                events.poll().await.unwrap()
+            {
+                let payload = serde_json::from_slice(&publish.payload).unwrap_or(Value::Null);
+                if publish.topic == topic && matches(&payload) {
+                    return payload;
+                }
+            }
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(10), wait)
+        .await
+        .unwrap_or_else(|_| panic!("nothing matching on {}", topic))
+}
+
+#[tokio::test]
+#[ignore = "needs an MQTT broker, such as mosquitto on localhost or one set in MQTT_BROKER"]
+async fn mqtt_bridges_the_lights_to_the_broker() {
+    use rumqttc::{AsyncClient, MqttOptions, QoS};
+
+    let cli = Cli::new("mqtt");
+    let light = MockLight::start(2).await.unwrap();
+    cli.ok(&["light", "add", "desk", &address(&light)]).await;
+    let broker = std::env::var("MQTT_BROKER").unwrap_or_else(|_| "localhost".to_string());
+    let parsed = elgato_light::mqtt::parse_broker(&broker).unwrap();
+    let prefix = format!("elgato-light-test-{}", std::process::id());
+
+    let (client, mut events) = AsyncClient::new(
+        MqttOptions::new(format!("{}-client", prefix), parsed.host, parsed.port),
+        16,
+    );
+    client
+        .subscribe(format!("{}/#", prefix), QoS::AtLeastOnce)
+        .await
+        .unwrap();
+    let discovery_prefix = format!("{}/homeassistant", prefix);
+    let _bridge = cli
+        .command(&[
+            "mqtt",
+            "--broker",
+            &broker,
+            "--prefix",
+            &prefix,
+            "--discovery-prefix",
+            &discovery_prefix,
+            "--interval",
+            "200ms",
+        ])
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    let config = mqtt_message(
+        &mut events,
+        &format!("{}/light/elgato_BW33J1A02740_1/config", discovery_prefix),
+        |_| true,
+    )
+    .await;
+    assert_eq!(config["name"], "Light 2");
+    assert_eq!(config["command_topic"], format!("{}/desk-1/set", prefix));
+    assert_eq!(config["supported_color_modes"][0], "color_temp");
+    assert_eq!(config["device"]["serial_number"], "BW33J1A02740");
+
+    let state_topic = format!("{}/desk-1/state", prefix);
+    let state = mqtt_message(&mut events, &state_topic, |_| true).await;
+    assert_eq!(state["state"], "OFF");
+
+    client
+        .publish(
+            format!("{}/desk-1/set", prefix),
+            QoS::AtLeastOnce,
+            false,
+            r#"{"state": "ON", "brightness": 40, "color_temp": 5000}"#,
+        )
+        .await
+        .unwrap();
+    let state = mqtt_message(&mut events, &state_topic, |state| state["state"] == "ON").await;
+    assert_eq!(state["brightness"], 40);
+    assert_eq!(light.lights()[1], light_state(1, 40, 5000));
+    assert_eq!(light.lights()[0].on, 0);
+
+    // Changes made on the light are picked up on the next read.
+    light.set_light(0, light_state(1, 70, 3200));
+    let state = mqtt_message(&mut events, &format!("{}/desk-0/state", prefix), |state| {
+        state["state"] == "ON"
+    })
+    .await;
+    assert_eq!(state["brightness"], 70);
+}